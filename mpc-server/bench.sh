@@ -0,0 +1,10 @@
+#!/bin/bash
+
+# runs the benchmarks with different rayon thread counts, see `benches/matching.rs`
+# usage: ./bench.sh [thread counts...]
+
+THREADS=${@:-1 2 4 8}
+
+for t in $THREADS; do
+    RAYON_NUM_THREADS=$t cargo bench --bench matching
+done
@@ -0,0 +1,93 @@
+// Benchmarks of the matching pipeline, on a matching pair.
+//
+// The MPC phases run in the 3 party threads of `run_match`, so they are measured with the
+// `comatch_mpc_phase_seconds` histogram of party 0. co-noir uses the global rayon pool, set its
+// size with `RAYON_NUM_THREADS` (see `bench.sh`): the thread count is the parameter of each
+// benchmark, so runs with different counts are kept apart.
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use serde_json::json;
+use std::time::Duration;
+
+use mpc_server::{
+    config::config,
+    matching::merge_shares,
+    metrics::PHASE_SECONDS,
+    shares::{Share, split_input},
+};
+
+use common::{decode_share, profile};
+
+/// Benchmark name and `phase` label of each MPC phase
+const PHASES: [(&str, &str); 4] = [
+    ("generate_witness_rep3", "witness"),
+    ("generate_proving_key_rep3", "proving_key"),
+    ("prove", "proof"),
+    ("verify", "verify"),
+];
+
+fn matching(c: &mut Criterion) {
+    let server = common::server();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let threads = rayon::current_num_threads();
+
+    let alice = profile(30, 0, 1, [2, 4, 6], (25, 35), 1);
+    let bob = profile(32, 1, 1, [1, 2, 3], (25, 35), 0);
+    let shares1 = runtime.block_on(server.split(&alice));
+    let shares2 = runtime.block_on(server.split(&bob));
+    let user1: [Share; 3] = std::array::from_fn(|i| decode_share(&shares1[i]));
+    let user2: [Share; 3] = std::array::from_fn(|i| decode_share(&shares2[3 + i]));
+    let merged: [Share; 3] =
+        std::array::from_fn(|i| merge_shares(user1[i].clone(), user2[i].clone()).unwrap());
+
+    let prover_path = config().tmp_dir.join("bench-Prover.toml");
+    std::fs::create_dir_all(&config().tmp_dir).unwrap();
+    std::fs::write(
+        &prover_path,
+        toml::to_string(&json!({ "user1": alice, "user2": bob })).unwrap(),
+    )
+    .unwrap();
+
+    let mut group = c.benchmark_group("matching");
+    // a match takes seconds
+    group.sample_size(10);
+
+    group.bench_function(BenchmarkId::new("split_input", threads), |b| {
+        b.iter(|| split_input(prover_path.clone(), &server.circuit.program_artifact).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("merge_input_shares", threads), |b| {
+        b.iter_batched(
+            || (user1[0].clone(), user2[0].clone()),
+            |(share1, share2)| merge_shares(share1, share2).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    for (name, phase) in PHASES {
+        group.bench_function(BenchmarkId::new(name, threads), |b| {
+            b.iter_custom(|iters| {
+                let histogram = PHASE_SECONDS.with_label_values(&[phase, "0"]);
+                let before = histogram.get_sample_sum();
+                for _ in 0..iters {
+                    server.run_match(merged.clone()).unwrap();
+                }
+                Duration::from_secs_f64(histogram.get_sample_sum() - before)
+            })
+        });
+    }
+
+    group.bench_function(BenchmarkId::new("run_match", threads), |b| {
+        b.iter_batched(
+            || merged.clone(),
+            |shares| server.run_match(shares).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, matching);
+criterion_main!(benches);
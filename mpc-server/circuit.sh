@@ -0,0 +1,17 @@
+#!/bin/bash
+
+# recompiles the Noir circuit and copies the artifact the server loads to data/circuit.json
+# the server reads it with noirc-artifacts 1.0.0-beta.4, install that nargo with `noirup -v 1.0.0-beta.4`
+
+set -e
+
+NARGO_VERSION=1.0.0-beta.4
+
+if ! nargo --version 2>/dev/null | grep -q "$NARGO_VERSION"; then
+    echo "nargo $NARGO_VERSION is required, run: noirup -v $NARGO_VERSION" >&2
+    exit 1
+fi
+
+rm -rf ../circuit/target
+(cd ../circuit && nargo compile)
+cp ../circuit/target/*.json data/circuit.json
@@ -0,0 +1,93 @@
+use jsonwebtoken::{Header, encode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{DeletionNotice, config},
+    db::{connect_db, delete_user, insert_notices, revoke_token},
+    error::Result,
+    keys::keys,
+    redact::Redacted,
+    token::{Token, now},
+};
+
+// Account deletion.
+//
+// Everything about the user is removed: the shares of every version, on both sides and for the
+// 3 parties, the user row, their evaluations (`checked`) and their matches. The user gets a
+// receipt signed like the access tokens, which can be checked against `/.well-known/jwks.json`.
+
+/// Claims of the deletion receipt
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletionReceipt {
+    /// `deletion_receipt`
+    pub typ: String,
+    /// The deleted user
+    pub sub: String,
+    pub iat: u64,
+    /// Share files removed from disk
+    pub share_files: usize,
+    /// Match rows removed: every pair with the user that passed the hard filters, whatever its
+    /// score and state
+    pub matches: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletionResponse {
+    pub receipt: Redacted<String>,
+}
+
+pub fn delete_account(token: &Token) -> Result<DeletionResponse> {
+    let mut conn = connect_db()?;
+    // the rows first: once the user is gone, the shares can't be matched anymore
+    let (counterparts, matches) = delete_user(&mut conn, &token.user_id)?;
+    revoke_token(&conn, &token.jti, token.exp)?;
+    let share_files = delete_shares(&token.user_id)?;
+
+    if config().deletion_notice == DeletionNotice::Notify {
+        insert_notices(&conn, &counterparts, "match_deleted")?;
+    }
+    tracing::info!(share_files, matches, "account deleted");
+
+    let key = keys()?.signing_key();
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    let receipt = encode(
+        &header,
+        &DeletionReceipt {
+            typ: "deletion_receipt".to_string(),
+            sub: token.user_id.clone(),
+            iat: now(),
+            share_files,
+            matches,
+        },
+        &key.encoding,
+    )?;
+
+    Ok(DeletionResponse {
+        receipt: receipt.into(),
+    })
+}
+
+/// Removes every share file of the user, whatever its version
+fn delete_shares(user_id: &str) -> Result<usize> {
+    // ids are alphanumeric, so no other user's files start with this prefix
+    let prefix = format!("{user_id}-");
+    let mut count = 0;
+    for dir in [config().shares_dir_1(), config().shares_dir_2()] {
+        if !dir.exists() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let is_share = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix));
+            if is_share {
+                std::fs::remove_file(&path)?;
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
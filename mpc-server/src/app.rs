@@ -0,0 +1,405 @@
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{Multipart, Path, Query, WebSocketUpgrade},
+    http::Request,
+    routing::{delete, get, post, put},
+};
+use co_noir::{AcirFormat, Bn254, CrsParser, Utils};
+use co_ultrahonk::prelude::{ProverCrs, ZeroKnowledge};
+use noirc_artifacts::program::ProgramArtifact;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tower_http::{
+    cors::{Any, CorsLayer},
+    trace::{self, TraceLayer},
+};
+use tracing::Level;
+
+use crate::{
+    account::delete_account,
+    chat::{self, KeyRequest, MessageRequest, Page},
+    config::config,
+    contacts::{self, Contact},
+    db::{connect_db, get_notices, get_share_versions},
+    error::{Error, Result},
+    health::{self, CircuitInfo},
+    keys,
+    matching::run_matches,
+    metrics,
+    moderation::{self, Admin, ReportQuery, ReportRequest, ReviewRequest},
+    profile,
+    recovery::{RecoverRequest, create_recovery_code, disable_recovery, recover},
+    redact::Redacted,
+    regions,
+    reveal::{self, Action, MatchQuery},
+    shares::{ProverData, replace_shares, rollback_shares, split_handler, upload},
+    token::{RefreshRequest, Token, logout, refresh},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    twitter_handle: Option<Redacted<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MinScoreRequest {
+    min_score: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContactsRequest {
+    contacts: Vec<Contact>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RollbackRequest {
+    version: u32,
+}
+
+/// The compiled circuit and its CRS
+pub struct Circuit {
+    pub program_artifact: ProgramArtifact,
+    pub constraint_system: Arc<AcirFormat<ark_bn254::Fr>>,
+    pub recursive: bool,
+    pub has_zk: ZeroKnowledge,
+    pub prover_crs: Arc<ProverCrs<Bn254>>,
+    pub verifier_crs: Arc<ark_bn254::G2Affine>,
+}
+
+impl Circuit {
+    /// Loads `circuit.json` from `data_dir` and the CRS from `config_dir`
+    pub fn load() -> Result<Self> {
+        let circuit_path = config().data_dir.join("circuit.json");
+        check_abi(&circuit_path)?;
+        let program_artifact = Utils::get_program_artifact_from_file(&circuit_path)
+            .map_err(|e| Error::Internal(format!("Invalid circuit: {e}")))?;
+        let constraint_system = Arc::new(Utils::get_constraint_system_from_artifact(
+            &program_artifact,
+            true,
+        ));
+
+        let recursive = true;
+        let has_zk = ZeroKnowledge::No;
+
+        let crs_size = co_noir::compute_circuit_size::<Bn254>(&constraint_system, recursive)
+            .map_err(|e| Error::Internal(e.to_string()))?;
+        let crs: (ProverCrs<Bn254>, ark_bn254::G2Affine) = CrsParser::<Bn254>::get_crs(
+            config().config_dir.join("bn254_g1.dat"),
+            config().config_dir.join("bn254_g2.dat"),
+            crs_size,
+            has_zk,
+        )
+        .map_err(|e| Error::Internal(format!("Invalid CRS: {e}")))?
+        .split();
+
+        health::init(CircuitInfo {
+            circuit_hash: health::circuit_hash(&circuit_path)?,
+            crs_size,
+        })?;
+
+        Ok(Circuit {
+            program_artifact,
+            constraint_system,
+            recursive,
+            has_zk,
+            prover_crs: Arc::new(crs.0),
+            verifier_crs: Arc::new(crs.1),
+        })
+    }
+}
+
+/// Checks that `circuit.json` was compiled from the current `circuit/`: an artifact built from an
+/// older circuit would still load, but every match would fail
+fn check_abi(circuit_path: &std::path::Path) -> Result<()> {
+    let artifact: serde_json::Value = serde_json::from_slice(&std::fs::read(circuit_path)?)
+        .map_err(|e| Error::Internal(format!("Invalid circuit: {e}")))?;
+    let abi = &artifact["abi"];
+    let has_field = |fields: &serde_json::Value, name: &str| {
+        fields
+            .as_array()
+            .is_some_and(|fields| fields.iter().any(|f| f["name"] == name))
+    };
+    let user = &abi["parameters"][0]["type"]["fields"];
+    let preferences = user
+        .as_array()
+        .and_then(|fields| fields.iter().find(|f| f["name"] == "preferences"))
+        .map(|f| &f["type"]["fields"]);
+    let score = json!({"kind": "integer", "sign": "unsigned", "width": 8});
+
+    let outdated = if abi["return_type"]["abi_type"] != score {
+        Some("doesn't return a score")
+    } else if !has_field(user, "location")
+        || !preferences.is_some_and(|fields| has_field(fields, "distance"))
+    {
+        Some("has no location or distance inputs")
+    } else {
+        None
+    };
+    match outdated {
+        Some(reason) => Err(Error::Internal(format!(
+            "circuit.json {reason}, rebuild it with `./circuit.sh`"
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub fn router(circuit: Arc<Circuit>) -> Router {
+    let cors = CorsLayer::new()
+        .allow_methods(Any)
+        .allow_origin(Any)
+        .allow_headers(Any);
+
+    let c = circuit.clone();
+
+    Router::new()
+        .route("/", get(|| async { "hello" }))
+        .route(
+            "/healthz",
+            get(|| async {
+                let (status, report) = health::healthz();
+                (status, Json(report))
+            }),
+        )
+        .route(
+            "/readyz",
+            get(|| async {
+                let (status, report) = health::readyz();
+                (status, Json(report))
+            }),
+        )
+        .route("/metrics", get(|_: Admin| async { metrics::render() }))
+        .route(
+            "/diagnostics",
+            get(|_: Admin| async { Json(health::diagnostics()) }),
+        )
+        .route("/regions", get(|| async { regions::registry().map(Json) }))
+        .route(
+            "/.well-known/jwks.json",
+            get(|| async { keys::keys().map(|keys| Json(json!(keys.jwks()))) }),
+        )
+        .route(
+            "/split",
+            post(|payload: Json<ProverData>| async move {
+                split_handler(payload.0, &c.program_artifact)
+                    .await
+                    .map(|shares| Json(json!({"shares": shares})))
+            }),
+        )
+        .route(
+            "/matches",
+            post(move |token: Token| async move {
+                run_matches(
+                    token.user_id,
+                    &circuit.program_artifact,
+                    circuit.constraint_system.clone(),
+                    circuit.recursive,
+                    circuit.has_zk,
+                    circuit.prover_crs.clone(),
+                    circuit.verifier_crs.clone(),
+                )
+                .await
+                .map(|_| Json("ok"))
+            }),
+        )
+        .route(
+            "/matches",
+            get(
+                move |token: Token, Query(query): Query<MatchQuery>| async move {
+                    reveal::get_matches(&token.user_id, query.state)
+                        .map(|matches| Json(json!({"matches": matches})))
+                },
+            ),
+        )
+        .route(
+            "/matches/{id}/accept",
+            post(|token: Token, Path(id): Path<i64>| async move {
+                reveal::respond(&token.user_id, id, Action::Accept).map(Json)
+            }),
+        )
+        .route(
+            "/matches/{id}/decline",
+            post(|token: Token, Path(id): Path<i64>| async move {
+                reveal::respond(&token.user_id, id, Action::Decline).map(Json)
+            }),
+        )
+        .route(
+            "/matches/{id}/unmatch",
+            post(|token: Token, Path(id): Path<i64>| async move {
+                reveal::respond(&token.user_id, id, Action::Unmatch).map(Json)
+            }),
+        )
+        .route(
+            "/matches/{id}/block",
+            post(|token: Token, Path(id): Path<i64>| async move {
+                moderation::block(&token.user_id, id).map(|_| Json(json!("ok")))
+            }),
+        )
+        .route(
+            "/matches/{id}/report",
+            post(
+                |token: Token, Path(id): Path<i64>, payload: Json<ReportRequest>| async move {
+                    moderation::report(&token.user_id, id, payload.0).map(|_| Json(json!("ok")))
+                },
+            ),
+        )
+        .route(
+            "/admin/reports",
+            get(|_: Admin, Query(query): Query<ReportQuery>| async move {
+                moderation::get_reports(query.status)
+                    .map(|reports| Json(json!({"reports": reports})))
+            }),
+        )
+        .route(
+            "/admin/reports/{id}",
+            post(
+                |_: Admin, Path(id): Path<i64>, payload: Json<ReviewRequest>| async move {
+                    moderation::review(id, payload.action).map(Json)
+                },
+            ),
+        )
+        .route(
+            "/profile",
+            get(|token: Token| async move { profile::status(&token.user_id).map(Json) }),
+        )
+        .route(
+            "/profile/min_score",
+            put(|token: Token, payload: Json<MinScoreRequest>| async move {
+                profile::set_min_score(&token.user_id, payload.min_score).map(|_| Json(json!("ok")))
+            }),
+        )
+        .route(
+            "/profile/shares",
+            get(|token: Token| async move {
+                get_share_versions(&connect_db()?, &token.user_id)
+                    .map(|versions| Json(json!({"versions": versions})))
+            }),
+        )
+        .route(
+            "/profile/shares",
+            put(|token: Token, multipart: Multipart| async move {
+                replace_shares(&token.user_id, multipart)
+                    .await
+                    .map(|version| Json(json!({"version": version})))
+            }),
+        )
+        .route(
+            "/profile/shares/rollback",
+            post(|token: Token, payload: Json<RollbackRequest>| async move {
+                rollback_shares(&token.user_id, payload.version).map(|_| Json(json!("ok")))
+            }),
+        )
+        .route(
+            "/matches/{id}/key",
+            put(
+                |token: Token, Path(id): Path<i64>, payload: Json<KeyRequest>| async move {
+                    chat::set_key(&token.user_id, id, &payload.public_key)
+                        .map(|_| Json(json!("ok")))
+                },
+            ),
+        )
+        .route(
+            "/matches/{id}/keys",
+            get(|token: Token, Path(id): Path<i64>| async move {
+                chat::get_keys(&token.user_id, id).map(Json)
+            }),
+        )
+        .route(
+            "/matches/{id}/messages",
+            get(
+                |token: Token, Path(id): Path<i64>, Query(page): Query<Page>| async move {
+                    chat::messages(&token.user_id, id, page)
+                        .map(|messages| Json(json!({"messages": messages})))
+                },
+            ),
+        )
+        .route(
+            "/matches/{id}/messages",
+            post(
+                |token: Token, Path(id): Path<i64>, payload: Json<MessageRequest>| async move {
+                    chat::send(&token.user_id, id, payload.0.ciphertext).map(Json)
+                },
+            ),
+        )
+        .route(
+            "/chat",
+            get(|ws: WebSocketUpgrade| async { ws.on_upgrade(chat::connect) }),
+        )
+        .route(
+            "/contacts",
+            get(|token: Token| async move {
+                contacts::get_contacts(&token.user_id)
+                    .map(|contacts| Json(json!({"contacts": contacts})))
+            }),
+        )
+        .route(
+            "/contacts",
+            put(|token: Token, payload: Json<ContactsRequest>| async move {
+                contacts::set_contacts(&token.user_id, payload.0.contacts)
+                    .map(|_| Json(json!("ok")))
+            }),
+        )
+        .route(
+            "/notices",
+            get(|token: Token| async move {
+                get_notices(&connect_db()?, &token.user_id)
+                    .map(|notices| Json(json!({"notices": notices})))
+            }),
+        )
+        .route(
+            "/account",
+            delete(|token: Token| async move { delete_account(&token).map(Json) }),
+        )
+        .route(
+            "/upload",
+            post(
+                |Query(query): Query<UploadQuery>, multipart: Multipart| async {
+                    upload(query.twitter_handle, multipart)
+                        .await
+                        .map(|tokens| Json(json!(tokens)))
+                },
+            ),
+        )
+        .route(
+            "/auth/refresh",
+            post(|payload: Json<RefreshRequest>| async move {
+                refresh(&payload).map(|tokens| Json(json!(tokens)))
+            }),
+        )
+        .route(
+            "/auth/logout",
+            post(|token: Token| async move { logout(&token).map(|_| Json(json!("ok"))) }),
+        )
+        .route(
+            "/auth/recovery",
+            post(|token: Token| async move {
+                create_recovery_code(&token.user_id).map(|code| Json(json!(code)))
+            }),
+        )
+        .route(
+            "/auth/recovery",
+            delete(|token: Token| async move {
+                disable_recovery(&token.user_id).map(|_| Json(json!("ok")))
+            }),
+        )
+        .route(
+            "/auth/recover",
+            post(|payload: Json<RecoverRequest>| async move {
+                recover(payload.recovery_code.expose()).map(|tokens| Json(json!(tokens)))
+            }),
+        )
+        .layer(cors)
+        .layer(
+            TraceLayer::new_for_http()
+                // the default span logs the full uri, and the query holds the twitter handle
+                .make_span_with(|request: &Request<Body>| {
+                    tracing::info_span!(
+                        "request",
+                        method = %request.method(),
+                        path = %request.uri().path(),
+                    )
+                })
+                .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
+        )
+}
@@ -1,31 +1,177 @@
-use color_eyre::{Result, eyre::Context};
-use rcgen::CertifiedKey;
-use std::path::PathBuf;
+use color_eyre::{
+    Result,
+    eyre::{Context, bail},
+};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    KeyUsagePurpose,
+};
+use std::path::{Path, PathBuf};
+use time::{Duration, OffsetDateTime};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 /// Certificate Generator for MPC-NET
 #[derive(Debug, PartialEq, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct CliArgs {
     /// The path to the .der certificate file
-    #[clap(short, long)]
-    cert_path: PathBuf,
+    #[clap(short, long, required = true)]
+    cert_path: Option<PathBuf>,
     /// The path to the .der key file
-    #[clap(short, long)]
-    key_path: PathBuf,
+    #[clap(short, long, required = true)]
+    key_path: Option<PathBuf>,
     /// The subject alternative names for the certificate
     #[clap(short, long)]
     sans: Vec<String>,
+    /// The validity of the certificate, in days
+    #[clap(long, default_value_t = 365)]
+    days: i64,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+enum Command {
+    /// Create a local CA and issue the certificates and keys of the 3 parties:
+    /// `ca.der`, `cert{0,1,2}.der` and `key{0,1,2}.der`
+    Bundle {
+        /// The output directory
+        #[clap(short, long, default_value = "config")]
+        out_dir: PathBuf,
+        /// Extra subject alternative names, added to every party certificate.
+        /// Party `i` always gets localhost, ip6-localhost, 127.0.0.1 and party{i}
+        #[clap(short, long)]
+        sans: Vec<String>,
+        /// Also write the CA key (.der) to this path, to issue more certificates later.
+        /// By default the CA key is discarded
+        #[clap(long)]
+        ca_key_path: Option<PathBuf>,
+        /// The validity of the CA and party certificates, in days
+        #[clap(long, default_value_t = 365)]
+        days: i64,
+        /// Overwrite existing files
+        #[clap(long)]
+        force: bool,
+    },
 }
 
 fn main() -> Result<()> {
     let args = CliArgs::parse();
 
-    let CertifiedKey { cert, key_pair } =
-        rcgen::generate_simple_self_signed(args.sans).context("generating self-signed cert")?;
+    match args.command {
+        Some(Command::Bundle {
+            out_dir,
+            sans,
+            ca_key_path,
+            days,
+            force,
+        }) => bundle(&out_dir, sans, ca_key_path, days, force),
+        None => self_signed(
+            args.cert_path.expect("required by clap"),
+            args.key_path.expect("required by clap"),
+            args.sans,
+            args.days,
+        ),
+    }
+}
+
+fn self_signed(cert_path: PathBuf, key_path: PathBuf, sans: Vec<String>, days: i64) -> Result<()> {
+    let key_pair = KeyPair::generate().context("generating key")?;
+    let mut params = CertificateParams::new(sans).context("invalid SAN")?;
+    set_validity(&mut params, days)?;
+    let cert = params
+        .self_signed(&key_pair)
+        .context("generating self-signed cert")?;
     let key = key_pair.serialize_der();
-    std::fs::write(args.key_path, key).context("writing key file")?;
+    std::fs::write(key_path, key).context("writing key file")?;
     let cert = cert.der();
-    std::fs::write(args.cert_path, cert).context("writing certificate file")?;
+    std::fs::write(cert_path, cert).context("writing certificate file")?;
+    Ok(())
+}
+
+fn bundle(
+    out_dir: &Path,
+    sans: Vec<String>,
+    ca_key_path: Option<PathBuf>,
+    days: i64,
+    force: bool,
+) -> Result<()> {
+    let mut outputs = vec![out_dir.join("ca.der")];
+    for i in 0..3 {
+        outputs.push(out_dir.join(format!("cert{i}.der")));
+        outputs.push(out_dir.join(format!("key{i}.der")));
+    }
+    outputs.extend(ca_key_path.clone());
+    if let Some(existing) = outputs.iter().find(|p| p.exists())
+        && !force
+    {
+        bail!(
+            "{} already exists, use --force to overwrite",
+            existing.display()
+        );
+    }
+    std::fs::create_dir_all(out_dir).context("creating output directory")?;
+
+    let ca_key = KeyPair::generate().context("generating CA key")?;
+    let mut ca_params = CertificateParams::default();
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "Co-Match MPC CA");
+    set_validity(&mut ca_params, days)?;
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let ca_cert = ca_params
+        .self_signed(&ca_key)
+        .context("generating CA cert")?;
+
+    for i in 0..3 {
+        let mut party_sans = vec![
+            "localhost".to_string(),
+            "ip6-localhost".to_string(),
+            "127.0.0.1".to_string(),
+            format!("party{i}"),
+        ];
+        party_sans.extend(sans.iter().cloned());
+
+        let key_pair = KeyPair::generate().context("generating party key")?;
+        let mut params = CertificateParams::new(party_sans).context("invalid SAN")?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, format!("party{i}"));
+        set_validity(&mut params, days)?;
+        params.use_authority_key_identifier_extension = true;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        let cert = params
+            .signed_by(&key_pair, &ca_cert, &ca_key)
+            .context("signing party cert")?;
+
+        std::fs::write(
+            out_dir.join(format!("key{i}.der")),
+            key_pair.serialize_der(),
+        )
+        .context("writing key file")?;
+        std::fs::write(out_dir.join(format!("cert{i}.der")), cert.der())
+            .context("writing certificate file")?;
+    }
+
+    std::fs::write(out_dir.join("ca.der"), ca_cert.der()).context("writing CA file")?;
+    if let Some(path) = ca_key_path {
+        std::fs::write(path, ca_key.serialize_der()).context("writing CA key file")?;
+    }
+
+    Ok(())
+}
+
+fn set_validity(params: &mut CertificateParams, days: i64) -> Result<()> {
+    if days <= 0 {
+        bail!("--days must be positive");
+    }
+    params.not_before = OffsetDateTime::now_utc();
+    params.not_after = params.not_before + Duration::days(days);
     Ok(())
 }
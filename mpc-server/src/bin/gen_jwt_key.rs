@@ -0,0 +1,37 @@
+use clap::{Parser, ValueEnum};
+use color_eyre::{Result, eyre::Context};
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ED25519};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+enum KeyAlgorithm {
+    Eddsa,
+    Es256,
+}
+
+/// JWT signing key generator.
+/// The file stem is used as the key id, e.g. `config/jwt/2025-06-01.pem`
+#[derive(Debug, PartialEq, Parser)]
+struct CliArgs {
+    /// The path to the .pem key file
+    #[clap(short, long)]
+    key_path: PathBuf,
+    /// The signature algorithm
+    #[clap(short, long, value_enum, default_value_t = KeyAlgorithm::Eddsa)]
+    algorithm: KeyAlgorithm,
+}
+
+fn main() -> Result<()> {
+    let args = CliArgs::parse();
+
+    let alg = match args.algorithm {
+        KeyAlgorithm::Eddsa => &PKCS_ED25519,
+        KeyAlgorithm::Es256 => &PKCS_ECDSA_P256_SHA256,
+    };
+    let key_pair = KeyPair::generate_for(alg).context("generating key pair")?;
+    if let Some(dir) = args.key_path.parent() {
+        std::fs::create_dir_all(dir).context("creating key directory")?;
+    }
+    std::fs::write(args.key_path, key_pair.serialize_pem()).context("writing key file")?;
+    Ok(())
+}
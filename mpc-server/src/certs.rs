@@ -0,0 +1,201 @@
+use once_cell::sync::OnceCell;
+use rcgen::KeyPair;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use sha2::{Digest, Sha256};
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::{
+    config::config,
+    error::{Error, Result},
+};
+
+// Certificates and keys of the MPC parties (`cert{0,1,2}.der`, `key{0,1,2}.der` in `config_dir`).
+//
+// The files are polled every `mpc.cert_reload_secs` and reloaded when their content changes (mtimes
+// aren't reliable: `cp -p` or a restored backup can move them back in time), so they can be
+// rotated without a restart. A new set is only used once all 6 files load and every key matches
+// its certificate, so a half-copied rotation is picked up on a later poll.
+// After a rotation the previous set is still accepted for `mpc.cert_overlap_secs`: a match that
+// fails on a network error with the new set is retried with the old one.
+
+static CERTS: OnceCell<RwLock<State>> = OnceCell::new();
+
+pub struct PartyCerts {
+    pub certs: [CertificateDer<'static>; 3],
+    keys: [PrivatePkcs8KeyDer<'static>; 3],
+    /// Expiry of each certificate, in seconds since the epoch
+    pub not_after: [i64; 3],
+}
+
+struct State {
+    current: Arc<PartyCerts>,
+    previous: Option<(Arc<PartyCerts>, Instant)>,
+    /// sha256 of the files the current set was loaded from
+    fingerprint: [u8; 32],
+}
+
+impl PartyCerts {
+    fn load(dir: &Path) -> Result<Self> {
+        let mut certs = Vec::with_capacity(3);
+        let mut keys = Vec::with_capacity(3);
+        let mut not_after = Vec::with_capacity(3);
+
+        for i in 0..3 {
+            let cert = std::fs::read(dir.join(format!("cert{i}.der")))?;
+            let key = std::fs::read(dir.join(format!("key{i}.der")))?;
+
+            let (_, parsed) = X509Certificate::from_der(&cert)
+                .map_err(|e| Error::Internal(format!("Invalid cert{i}.der: {e}")))?;
+            let key_pair = KeyPair::try_from(key.as_slice())
+                .map_err(|e| Error::Internal(format!("Invalid key{i}.der: {e}")))?;
+            if key_pair.public_key_raw() != parsed.public_key().subject_public_key.data.as_ref() {
+                return Err(Error::Internal(format!(
+                    "key{i}.der doesn't match cert{i}.der"
+                )));
+            }
+            not_after.push(parsed.validity().not_after.timestamp());
+
+            certs.push(CertificateDer::from(cert));
+            keys.push(PrivatePkcs8KeyDer::from(key));
+        }
+
+        Ok(PartyCerts {
+            certs: certs.try_into().expect("3 certs"),
+            keys: keys.try_into().expect("3 keys"),
+            not_after: not_after.try_into().expect("3 certs"),
+        })
+    }
+
+    pub fn key(&self, party: usize) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Pkcs8(self.keys[party].clone_key())
+    }
+
+    /// Parties whose certificate expires in less than `mpc.cert_expiry_warning_days`,
+    /// with the number of days left (negative if already expired)
+    pub fn expiring(&self) -> Vec<(usize, i64)> {
+        let now = unix_now();
+        let threshold = config().mpc.cert_expiry_warning_days as i64;
+        self.not_after
+            .iter()
+            .enumerate()
+            .map(|(party, not_after)| (party, (not_after - now).div_euclid(86400)))
+            .filter(|(_, days_left)| *days_left < threshold)
+            .collect()
+    }
+
+    fn warn_expiring(&self) {
+        for (party, days_left) in self.expiring() {
+            if days_left < 0 {
+                tracing::error!("cert{party}.der has expired");
+            } else {
+                tracing::warn!("cert{party}.der expires in {days_left} days");
+            }
+        }
+    }
+}
+
+/// Loads the party certificates. Must be called before `current` or `accepted`.
+pub fn init() -> Result<()> {
+    let dir = &config().config_dir;
+    let fingerprint = fingerprint(dir)?;
+    let current = Arc::new(PartyCerts::load(dir)?);
+    current.warn_expiring();
+
+    CERTS
+        .set(RwLock::new(State {
+            current,
+            previous: None,
+            fingerprint,
+        }))
+        .map_err(|_| Error::Internal("Certificates already loaded".to_string()))
+}
+
+fn state() -> Result<&'static RwLock<State>> {
+    CERTS
+        .get()
+        .ok_or(Error::Internal("Certificates not loaded".to_string()))
+}
+
+pub fn current() -> Result<Arc<PartyCerts>> {
+    let state = state()?.read().expect("certs lock poisoned");
+    Ok(state.current.clone())
+}
+
+/// The current set, then the previous one while the overlap window is open
+pub fn accepted() -> Result<Vec<Arc<PartyCerts>>> {
+    let state = state()?.read().expect("certs lock poisoned");
+    let mut accepted = vec![state.current.clone()];
+    if let Some((previous, until)) = &state.previous
+        && Instant::now() < *until
+    {
+        accepted.push(previous.clone());
+    }
+    Ok(accepted)
+}
+
+/// Reloads the certificates if any of the files changed since the last load.
+/// Returns true if a new set was loaded.
+pub fn reload_if_changed() -> Result<bool> {
+    let dir = &config().config_dir;
+    let fingerprint = fingerprint(dir)?;
+    if fingerprint == state()?.read().expect("certs lock poisoned").fingerprint {
+        return Ok(false);
+    }
+
+    let new = Arc::new(PartyCerts::load(dir)?);
+    new.warn_expiring();
+
+    let mut state = state()?.write().expect("certs lock poisoned");
+    let overlap = Duration::from_secs(config().mpc.cert_overlap_secs);
+    let old = std::mem::replace(&mut state.current, new);
+    state.previous = Some((old, Instant::now() + overlap));
+    state.fingerprint = fingerprint;
+    Ok(true)
+}
+
+/// Polls the certificate files until the server stops, and repeats the expiry warnings daily
+pub async fn watch() {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config().mpc.cert_reload_secs.max(1)));
+    let mut last_warning = Instant::now();
+    loop {
+        interval.tick().await;
+        match reload_if_changed() {
+            Ok(true) => {
+                tracing::info!("reloaded MPC party certificates");
+                last_warning = Instant::now();
+            }
+            Ok(false) => {}
+            Err(e) => tracing::warn!("failed to reload MPC party certificates: {e}"),
+        }
+
+        if last_warning.elapsed() > Duration::from_secs(86400) {
+            if let Ok(current) = current() {
+                current.warn_expiring();
+            }
+            last_warning = Instant::now();
+        }
+    }
+}
+
+fn fingerprint(dir: &Path) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    for i in 0..3 {
+        for file in [format!("cert{i}.der"), format!("key{i}.der")] {
+            hasher.update(std::fs::read(dir.join(file))?);
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
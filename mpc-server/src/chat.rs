@@ -0,0 +1,236 @@
+use axum::extract::ws::{Message, WebSocket};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    config::config,
+    db::{self, MatchRow, MessageRow, connect_db, get_chat_key, insert_message, set_chat_key},
+    error::{AuthError, Error, Result},
+    reveal, token,
+};
+
+// End-to-end encrypted chat between matched users.
+//
+// A conversation belongs to a revealed match and only its two users can access it. Each user
+// publishes a public key for the conversation, and the clients encrypt the messages with them:
+// the server only stores and relays ciphertext.
+//
+// Messages are sent with `POST /matches/{id}/messages` and pushed to the recipient on
+// `GET /chat`, a WebSocket. Browsers can't set headers on a WebSocket, so the first message on
+// the socket must be the access token. The server answers `{"ready": true}`, then sends every new
+// message for the user.
+// The socket lives as long as the token it was opened with: every `chat_check_secs`, it checks that
+// the token hasn't expired or been revoked (logout, account deletion) and that the user isn't
+// suspended, and closes otherwise. The client reconnects with a fresh token.
+
+const MAX_KEY_LEN: usize = 256;
+const MAX_CIPHERTEXT_LEN: usize = 8192;
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages to deliver, each socket only forwards the ones for its user
+static EVENTS: Lazy<broadcast::Sender<Delivery>> = Lazy::new(|| broadcast::channel(1024).0);
+
+#[derive(Debug, Clone)]
+struct Delivery {
+    recipient_id: String,
+    message: ChatMessage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub match_id: i64,
+    /// Sent by the user reading it
+    pub mine: bool,
+    /// Base64, encrypted by the sender's client
+    pub ciphertext: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatKeys {
+    pub mine: Option<String>,
+    /// `None` until the other user published theirs
+    pub theirs: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyRequest {
+    /// Base64
+    pub public_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageRequest {
+    pub ciphertext: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    /// Id of the oldest message already loaded
+    pub before: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+/// The match, if the user can chat in it
+fn conversation(conn: &rusqlite::Connection, user_id: &str, match_id: i64) -> Result<MatchRow> {
+    let row = db::get_match(conn, user_id, match_id)?;
+    if !reveal::is_revealed(&row)? {
+        return Err(Error::Conflict(
+            "The chat is only open while the match is revealed".to_string(),
+        ));
+    }
+    Ok(row)
+}
+
+fn check_base64(value: &str, max_len: usize, what: &str) -> Result<()> {
+    if value.is_empty() || value.len() > max_len || STANDARD.decode(value).is_err() {
+        return Err(Error::Validation(format!("Invalid {what}")));
+    }
+    Ok(())
+}
+
+pub fn set_key(user_id: &str, match_id: i64, public_key: &str) -> Result<()> {
+    check_base64(public_key, MAX_KEY_LEN, "public key")?;
+    let conn = connect_db()?;
+    conversation(&conn, user_id, match_id)?;
+    set_chat_key(&conn, match_id, user_id, public_key)
+}
+
+pub fn get_keys(user_id: &str, match_id: i64) -> Result<ChatKeys> {
+    let conn = connect_db()?;
+    let row = conversation(&conn, user_id, match_id)?;
+    Ok(ChatKeys {
+        mine: get_chat_key(&conn, match_id, user_id)?,
+        theirs: get_chat_key(&conn, match_id, &row.other_id)?,
+    })
+}
+
+/// Stores the message and pushes it to the other user if they are connected
+pub fn send(user_id: &str, match_id: i64, ciphertext: String) -> Result<ChatMessage> {
+    check_base64(&ciphertext, MAX_CIPHERTEXT_LEN, "ciphertext")?;
+    let conn = connect_db()?;
+    let row = conversation(&conn, user_id, match_id)?;
+    let (id, created_at) = insert_message(&conn, match_id, user_id, &ciphertext)?;
+
+    let message = ChatMessage {
+        id,
+        match_id,
+        mine: true,
+        ciphertext,
+        created_at,
+    };
+    // no receiver is not an error, the message is fetched later
+    let _ = EVENTS.send(Delivery {
+        recipient_id: row.other_id,
+        message: ChatMessage {
+            mine: false,
+            ..message.clone()
+        },
+    });
+    Ok(message)
+}
+
+/// A page of the conversation, newest first
+pub fn messages(user_id: &str, match_id: i64, page: Page) -> Result<Vec<ChatMessage>> {
+    let limit = page
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let conn = connect_db()?;
+    conversation(&conn, user_id, match_id)?;
+    Ok(db::get_messages(&conn, match_id, page.before, limit)?
+        .into_iter()
+        .map(|m: MessageRow| ChatMessage {
+            id: m.id,
+            match_id,
+            mine: m.sender_id == user_id,
+            ciphertext: m.ciphertext,
+            created_at: m.created_at,
+        })
+        .collect())
+}
+
+/// Pushes the user's new messages on the socket until it closes
+pub async fn connect(mut socket: WebSocket) {
+    let token = match authenticate(&mut socket).await {
+        Ok(token) => token,
+        Err(e) => return send_error(&mut socket, e).await,
+    };
+    let user_id = token.user_id.clone();
+
+    let mut events = EVENTS.subscribe();
+    let period = Duration::from_secs(config().chat_check_secs);
+    let mut checks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    let ready = serde_json::json!({"ready": true}).to_string();
+    if socket.send(Message::Text(ready.into())).await.is_err() {
+        return;
+    }
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(delivery) if delivery.recipient_id == user_id => {
+                    let Ok(message) = serde_json::to_string(&delivery.message) else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(message.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                // the client catches up with `GET /matches/{id}/messages`
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "chat socket lagging");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // pings are answered by axum, clients don't send anything else
+                Some(Ok(_)) => {}
+            },
+            _ = checks.tick() => {
+                if let Err(e) = check_session(&token) {
+                    return send_error(&mut socket, e).await;
+                }
+            }
+        }
+    }
+}
+
+/// The token must still be valid, and its user must still exist and not be suspended
+fn check_session(token: &token::Token) -> Result<()> {
+    if token.exp <= token::now() {
+        return Err(AuthError::ExpiredToken.into());
+    }
+    let conn = connect_db()?;
+    if db::is_token_revoked(&conn, &token.jti)? {
+        return Err(AuthError::RevokedToken.into());
+    }
+    if db::get_user(&conn, &token.user_id)?.suspended {
+        return Err(Error::Forbidden("Account suspended".to_string()));
+    }
+    Ok(())
+}
+
+async fn send_error(socket: &mut WebSocket, e: Error) {
+    let _ = socket
+        .send(Message::Text(e.to_json().to_string().into()))
+        .await;
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+async fn authenticate(socket: &mut WebSocket) -> Result<token::Token> {
+    let first = tokio::time::timeout(AUTH_TIMEOUT, socket.recv())
+        .await
+        .map_err(|_| AuthError::MissingToken)?;
+    match first {
+        Some(Ok(Message::Text(token))) => token::verify(token.as_str()),
+        _ => Err(AuthError::MissingToken.into()),
+    }
+}
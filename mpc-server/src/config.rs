@@ -0,0 +1,242 @@
+use clap::Args;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::error::{Error, Result};
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Server configuration.
+/// Values come from the CLI or `COMATCH_*` env variables first, then the TOML config file,
+/// then the defaults. Relative paths are resolved from the working directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the HTTPS server listens on
+    pub bind: SocketAddr,
+    /// Circuit, database and shares
+    pub data_dir: PathBuf,
+    /// MPC parties certificates and keys, CRS
+    pub config_dir: PathBuf,
+    /// TLS certificate (`cert.pem`) and key (`key.pem`) of the HTTPS server
+    pub ssl_dir: PathBuf,
+    /// Temporary prover inputs
+    pub tmp_dir: PathBuf,
+    /// What the matches of a user are told when they delete their account
+    pub deletion_notice: DeletionNotice,
+    /// Matches not accepted by both users within this many seconds expire, 0 to keep them forever
+    pub match_ttl_secs: u64,
+    /// How often expired matches are swept, in seconds
+    pub match_sweep_secs: u64,
+    /// How often chat sockets check that their token and user are still valid, in seconds
+    pub chat_check_secs: u64,
+    pub mpc: MpcConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionNotice {
+    /// The match disappears from their list
+    #[default]
+    Silent,
+    /// They also get a `match_deleted` notice, see `GET /notices`
+    Notify,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MpcConfig {
+    /// Number of matches computed in parallel
+    pub threads: usize,
+    /// MPC network timeout, in seconds
+    pub timeout_secs: u64,
+    /// Base port of each party. Match `i` of a batch uses `party_ports[party] + i`
+    pub party_ports: [u16; 3],
+    /// How often the party certificates are checked for changes, in seconds
+    pub cert_reload_secs: u64,
+    /// How long the previous certificates are still accepted after a rotation, in seconds
+    pub cert_overlap_secs: u64,
+    /// Warn when a party certificate expires in less than this many days
+    pub cert_expiry_warning_days: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            data_dir: PathBuf::from("data"),
+            config_dir: PathBuf::from("config"),
+            ssl_dir: PathBuf::from("ssl-cert"),
+            tmp_dir: PathBuf::from("tmp"),
+            deletion_notice: DeletionNotice::default(),
+            match_ttl_secs: 14 * 24 * 3600,
+            match_sweep_secs: 3600,
+            chat_check_secs: 30,
+            mpc: MpcConfig::default(),
+        }
+    }
+}
+
+impl Default for MpcConfig {
+    fn default() -> Self {
+        MpcConfig {
+            threads: 5,
+            timeout_secs: 60,
+            party_ports: [10000, 11000, 12000],
+            cert_reload_secs: 30,
+            cert_overlap_secs: 3600,
+            cert_expiry_warning_days: 30,
+        }
+    }
+}
+
+impl Config {
+    pub fn shares_dir_1(&self) -> PathBuf {
+        self.data_dir.join("user1")
+    }
+
+    pub fn shares_dir_2(&self) -> PathBuf {
+        self.data_dir.join("user2")
+    }
+
+    pub fn mpc_timeout(&self) -> Duration {
+        Duration::from_secs(self.mpc.timeout_secs)
+    }
+
+    /// When a match created at `created_at` expires, if it does
+    pub fn match_expiry(&self, created_at: u64) -> Option<u64> {
+        (self.match_ttl_secs > 0).then(|| created_at + self.match_ttl_secs)
+    }
+}
+
+/// Config overrides from the command line or the environment
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// Path to the TOML config file
+    #[arg(short, long, env = "COMATCH_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "COMATCH_BIND")]
+    pub bind: Option<SocketAddr>,
+    #[arg(long, env = "COMATCH_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    #[arg(long, env = "COMATCH_CONFIG_DIR")]
+    pub config_dir: Option<PathBuf>,
+    #[arg(long, env = "COMATCH_SSL_DIR")]
+    pub ssl_dir: Option<PathBuf>,
+    #[arg(long, env = "COMATCH_TMP_DIR")]
+    pub tmp_dir: Option<PathBuf>,
+    #[arg(long, env = "COMATCH_DELETION_NOTICE", value_enum)]
+    pub deletion_notice: Option<DeletionNotice>,
+    #[arg(long, env = "COMATCH_MATCH_TTL_SECS")]
+    pub match_ttl_secs: Option<u64>,
+    #[arg(long, env = "COMATCH_MATCH_SWEEP_SECS")]
+    pub match_sweep_secs: Option<u64>,
+    #[arg(long, env = "COMATCH_CHAT_CHECK_SECS")]
+    pub chat_check_secs: Option<u64>,
+    #[arg(long, env = "COMATCH_MPC_THREADS")]
+    pub mpc_threads: Option<usize>,
+    #[arg(long, env = "COMATCH_MPC_TIMEOUT_SECS")]
+    pub mpc_timeout_secs: Option<u64>,
+    /// Base ports of the 3 parties, comma separated
+    #[arg(long, env = "COMATCH_MPC_PARTY_PORTS", value_delimiter = ',')]
+    pub mpc_party_ports: Option<Vec<u16>>,
+    #[arg(long, env = "COMATCH_MPC_CERT_RELOAD_SECS")]
+    pub mpc_cert_reload_secs: Option<u64>,
+    #[arg(long, env = "COMATCH_MPC_CERT_OVERLAP_SECS")]
+    pub mpc_cert_overlap_secs: Option<u64>,
+    #[arg(long, env = "COMATCH_MPC_CERT_EXPIRY_WARNING_DAYS")]
+    pub mpc_cert_expiry_warning_days: Option<u64>,
+}
+
+impl ConfigArgs {
+    pub fn load(self) -> Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)?;
+                toml::from_str(&content).map_err(|e| {
+                    Error::Internal(format!("Invalid config file {}: {e}", path.display()))
+                })?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(data_dir) = self.data_dir {
+            config.data_dir = data_dir;
+        }
+        if let Some(config_dir) = self.config_dir {
+            config.config_dir = config_dir;
+        }
+        if let Some(ssl_dir) = self.ssl_dir {
+            config.ssl_dir = ssl_dir;
+        }
+        if let Some(tmp_dir) = self.tmp_dir {
+            config.tmp_dir = tmp_dir;
+        }
+        if let Some(deletion_notice) = self.deletion_notice {
+            config.deletion_notice = deletion_notice;
+        }
+        if let Some(ttl_secs) = self.match_ttl_secs {
+            config.match_ttl_secs = ttl_secs;
+        }
+        if let Some(sweep_secs) = self.match_sweep_secs {
+            config.match_sweep_secs = sweep_secs;
+        }
+        if let Some(check_secs) = self.chat_check_secs {
+            config.chat_check_secs = check_secs;
+        }
+        if let Some(threads) = self.mpc_threads {
+            config.mpc.threads = threads;
+        }
+        if let Some(timeout_secs) = self.mpc_timeout_secs {
+            config.mpc.timeout_secs = timeout_secs;
+        }
+        if let Some(ports) = self.mpc_party_ports {
+            config.mpc.party_ports = ports.try_into().map_err(|_| {
+                Error::Validation("mpc-party-ports takes exactly 3 ports".to_string())
+            })?;
+        }
+        if let Some(reload_secs) = self.mpc_cert_reload_secs {
+            config.mpc.cert_reload_secs = reload_secs;
+        }
+        if let Some(overlap_secs) = self.mpc_cert_overlap_secs {
+            config.mpc.cert_overlap_secs = overlap_secs;
+        }
+        if let Some(warning_days) = self.mpc_cert_expiry_warning_days {
+            config.mpc.cert_expiry_warning_days = warning_days;
+        }
+
+        if config.match_sweep_secs == 0 {
+            return Err(Error::Validation(
+                "match_sweep_secs must be at least 1".to_string(),
+            ));
+        }
+        if config.chat_check_secs == 0 {
+            return Err(Error::Validation(
+                "chat_check_secs must be at least 1".to_string(),
+            ));
+        }
+        if config.mpc.threads == 0 {
+            return Err(Error::Validation(
+                "mpc.threads must be at least 1".to_string(),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Sets the global config. Must be called before anything reads it.
+pub fn init(config: Config) -> Result<()> {
+    CONFIG
+        .set(config)
+        .map_err(|_| Error::Internal("Config already initialized".to_string()))
+}
+
+/// The global config, or the defaults if `init` wasn't called (e.g. in tests)
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
@@ -0,0 +1,268 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::{
+    db::{self, connect_db},
+    error::{Error, Result},
+};
+
+// Contact channels.
+//
+// A user can attach several contacts, each with a visibility: `matches` ones are revealed to the
+// matches both users accepted, `hidden` ones are kept for later. Values are sealed at rest like
+// the shares, and validated per channel.
+
+const MAX_CONTACTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// Twitter/X handle
+    Twitter,
+    Telegram,
+    /// Username (`name.01`) or phone number
+    Signal,
+    Email,
+    Farcaster,
+    /// `npub1...`
+    Nostr,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Revealed to accepted matches
+    #[default]
+    Matches,
+    Hidden,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contact {
+    pub channel: Channel,
+    pub value: String,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// A contact as shown to a match
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedContact {
+    pub channel: Channel,
+    pub value: String,
+}
+
+impl Channel {
+    fn as_str(self) -> &'static str {
+        match self {
+            Channel::Twitter => "twitter",
+            Channel::Telegram => "telegram",
+            Channel::Signal => "signal",
+            Channel::Email => "email",
+            Channel::Farcaster => "farcaster",
+            Channel::Nostr => "nostr",
+        }
+    }
+
+    fn parse(channel: &str) -> Option<Self> {
+        Some(match channel {
+            "twitter" => Channel::Twitter,
+            "telegram" => Channel::Telegram,
+            "signal" => Channel::Signal,
+            "email" => Channel::Email,
+            "farcaster" => Channel::Farcaster,
+            "nostr" => Channel::Nostr,
+            _ => return None,
+        })
+    }
+
+    /// The value in its canonical form, or `None` if it isn't valid for the channel
+    pub fn normalize(self, value: &str) -> Option<String> {
+        let value = value.trim();
+        let handle = value.strip_prefix('@').unwrap_or(value);
+        let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let valid = match self {
+            Channel::Twitter => (1..=15).contains(&handle.len()) && handle.chars().all(word),
+            Channel::Telegram => {
+                (5..=32).contains(&handle.len())
+                    && handle.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && handle.chars().all(word)
+            }
+            Channel::Signal => is_signal(value),
+            Channel::Email => is_email(value),
+            Channel::Farcaster => is_fname(handle),
+            Channel::Nostr => is_npub(value),
+        };
+        if !valid {
+            return None;
+        }
+        Some(match self {
+            Channel::Twitter | Channel::Telegram => handle.to_string(),
+            Channel::Farcaster => handle.to_ascii_lowercase(),
+            Channel::Email => value.to_ascii_lowercase(),
+            Channel::Signal | Channel::Nostr => value.to_string(),
+        })
+    }
+}
+
+impl Visibility {
+    fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Matches => "matches",
+            Visibility::Hidden => "hidden",
+        }
+    }
+}
+
+impl ToSql for Channel {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Channel {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Channel::parse(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl ToSql for Visibility {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Visibility {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "matches" => Ok(Visibility::Matches),
+            "hidden" => Ok(Visibility::Hidden),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Signal username (`name.01`) or phone number in international format
+fn is_signal(value: &str) -> bool {
+    if let Some(number) = value.strip_prefix('+') {
+        return (8..=15).contains(&number.len()) && number.chars().all(|c| c.is_ascii_digit());
+    }
+    let Some((name, discriminator)) = value.rsplit_once('.') else {
+        return false;
+    };
+    (3..=32).contains(&name.len())
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && (2..=9).contains(&discriminator.len())
+        && discriminator.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Only catches typos, the address isn't checked
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    let labels: Vec<&str> = domain.split('.').collect();
+    value.len() <= 254
+        && (1..=64).contains(&local.len())
+        && !value.chars().any(|c| c.is_whitespace() || c.is_control())
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Farcaster username, or an ENS name
+fn is_fname(value: &str) -> bool {
+    let name = value.strip_suffix(".eth").unwrap_or(value);
+    (1..=16).contains(&name.len())
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Bech32 encoded 32 bytes public key, with a valid checksum
+fn is_npub(value: &str) -> bool {
+    const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+    let Some(data) = value.strip_prefix("npub1") else {
+        return false;
+    };
+    // 52 characters of key, 6 of checksum
+    if data.len() != 58 {
+        return false;
+    }
+    let Some(data) = data
+        .bytes()
+        .map(|c| CHARSET.iter().position(|&b| b == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+    else {
+        return false;
+    };
+
+    let hrp = b"npub";
+    let values = hrp
+        .iter()
+        .map(|c| c >> 5)
+        .chain([0])
+        .chain(hrp.iter().map(|c| c & 31))
+        .chain(data);
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum == 1
+}
+
+/// Checks and normalizes a list of contacts
+pub fn validate(contacts: Vec<Contact>) -> Result<Vec<Contact>> {
+    if contacts.len() > MAX_CONTACTS {
+        return Err(Error::Validation(format!(
+            "At most {MAX_CONTACTS} contacts"
+        )));
+    }
+    let mut seen = HashSet::new();
+    contacts
+        .into_iter()
+        .map(|contact| {
+            let value = contact.channel.normalize(&contact.value).ok_or_else(|| {
+                Error::Validation(format!("Invalid {} contact", contact.channel.as_str()))
+            })?;
+            if !seen.insert((contact.channel, value.clone())) {
+                return Err(Error::Validation("Duplicate contact".to_string()));
+            }
+            Ok(Contact { value, ..contact })
+        })
+        .collect()
+}
+
+pub fn get_contacts(user_id: &str) -> Result<Vec<Contact>> {
+    db::get_contacts(&connect_db()?, user_id)
+}
+
+/// Replaces all the contacts of the user
+pub fn set_contacts(user_id: &str, contacts: Vec<Contact>) -> Result<()> {
+    let contacts = validate(contacts)?;
+    db::replace_contacts(&mut connect_db()?, user_id, &contacts)
+}
+
+/// The contacts a match can see
+pub fn shared_contacts(conn: &rusqlite::Connection, user_id: &str) -> Result<Vec<SharedContact>> {
+    Ok(db::get_contacts(conn, user_id)?
+        .into_iter()
+        .filter(|c| c.visibility == Visibility::Matches)
+        .map(|c| SharedContact {
+            channel: c.channel,
+            value: c.value,
+        })
+        .collect())
+}
@@ -0,0 +1,199 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+// Envelope encryption for data at rest.
+//
+// Every blob gets its own random data key (DEK), which encrypts the payload. The DEK is then
+// wrapped with the master key (KEK). Rotating the master key only requires re-wrapping the DEKs,
+// see `rewrap`.
+//
+// Layout of a sealed blob:
+// MAGIC (4) | VERSION (1) | kid (8) | wrap nonce (12) | wrapped DEK (48) | data nonce (12) | ciphertext
+
+const MAGIC: &[u8; 4] = b"CMEK";
+const VERSION: u8 = 1;
+const KID_LEN: usize = 8;
+const NONCE_LEN: usize = 12;
+const WRAPPED_DEK_LEN: usize = 32 + 16;
+const HEADER_LEN: usize = MAGIC.len() + 1 + KID_LEN + NONCE_LEN + WRAPPED_DEK_LEN + NONCE_LEN;
+
+static KEYRING: OnceCell<Keyring> = OnceCell::new();
+
+struct MasterKey {
+    id: [u8; KID_LEN],
+    cipher: Aes256Gcm,
+}
+
+impl MasterKey {
+    fn from_hex(hex_key: &str) -> Result<Self> {
+        let bytes = hex::decode(hex_key.trim())
+            .map_err(|_| Error::Internal("Master key must be hex encoded".to_string()))?;
+        if bytes.len() != 32 {
+            return Err(Error::Internal(
+                "Master key must be 32 bytes (64 hex chars)".to_string(),
+            ));
+        }
+        let mut id = [0u8; KID_LEN];
+        id.copy_from_slice(&Sha256::digest(&bytes)[..KID_LEN]);
+        Ok(MasterKey {
+            id,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)),
+        })
+    }
+}
+
+/// The current master key, used for sealing, and the previous ones, only used for opening.
+pub struct Keyring {
+    current: MasterKey,
+    previous: Vec<MasterKey>,
+}
+
+impl Keyring {
+    /// Reads the master keys from `MASTER_KEY`, or from the file at `MASTER_KEY_FILE`.
+    /// Keys are hex encoded, separated by commas or newlines, the first one being the current key.
+    fn from_env() -> Result<Self> {
+        let keys = match (
+            std::env::var("MASTER_KEY"),
+            std::env::var("MASTER_KEY_FILE"),
+        ) {
+            (Ok(keys), _) => keys,
+            (Err(_), Ok(path)) => std::fs::read_to_string(path)?,
+            _ => {
+                return Err(Error::Internal(
+                    "MASTER_KEY or MASTER_KEY_FILE must be set".to_string(),
+                ));
+            }
+        };
+
+        let mut keys = keys
+            .split([',', '\n'])
+            .filter(|k| !k.trim().is_empty())
+            .map(MasterKey::from_hex)
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err(Error::Internal("No master key found".to_string()));
+        }
+        let current = keys.remove(0);
+
+        Ok(Keyring {
+            current,
+            previous: keys,
+        })
+    }
+
+    fn get(&self, id: &[u8]) -> Option<&MasterKey> {
+        std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|k| k.id == id)
+    }
+}
+
+pub fn keyring() -> Result<&'static Keyring> {
+    KEYRING.get_or_try_init(Keyring::from_env)
+}
+
+/// Returns true if `data` looks like a blob produced by `seal`.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data.starts_with(MAGIC) && data[MAGIC.len()] == VERSION
+}
+
+pub fn seal(plaintext: &[u8]) -> Result<Vec<u8>> {
+    let dek = Aes256Gcm::generate_key(OsRng);
+    let data_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(&dek)
+        .encrypt(&data_nonce, plaintext)
+        .map_err(|_| Error::Storage("Encryption failed".to_string()))?;
+
+    let mut out = wrap_header(keyring()?, &dek)?;
+    out.extend_from_slice(&data_nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+pub fn open(sealed: &[u8]) -> Result<Vec<u8>> {
+    let dek = unwrap_dek(keyring()?, sealed)?;
+    let data_nonce = Nonce::from_slice(&sealed[HEADER_LEN - NONCE_LEN..HEADER_LEN]);
+    let plaintext = Aes256Gcm::new(&dek)
+        .decrypt(data_nonce, &sealed[HEADER_LEN..])
+        .map_err(|_| Error::Storage("Decryption failed".to_string()))?;
+    Ok(plaintext)
+}
+
+/// Seals a string and hex encodes it, so it can be stored in a TEXT column.
+pub fn seal_str(plaintext: &str) -> Result<String> {
+    Ok(hex::encode(seal(plaintext.as_bytes())?))
+}
+
+pub fn open_str(sealed: &str) -> Result<String> {
+    let sealed =
+        hex::decode(sealed).map_err(|_| Error::Storage("Data is not sealed".to_string()))?;
+    String::from_utf8(open(&sealed)?).map_err(|e| Error::Storage(e.to_string()))
+}
+
+/// Re-wraps the data key of a sealed blob with the current master key.
+/// The payload itself is not re-encrypted.
+pub fn rewrap(sealed: &[u8]) -> Result<Vec<u8>> {
+    let keyring = keyring()?;
+    let dek = unwrap_dek(keyring, sealed)?;
+    let mut out = wrap_header(keyring, &dek)?;
+    out.extend_from_slice(&sealed[HEADER_LEN - NONCE_LEN..]);
+    Ok(out)
+}
+
+fn wrap_header(keyring: &Keyring, dek: &Key<Aes256Gcm>) -> Result<Vec<u8>> {
+    let kek = &keyring.current;
+    let wrap_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let wrapped_dek = kek
+        .cipher
+        .encrypt(
+            &wrap_nonce,
+            Payload {
+                msg: dek.as_slice(),
+                aad: &kek.id,
+            },
+        )
+        .map_err(|_| Error::Storage("Encryption failed".to_string()))?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&kek.id);
+    out.extend_from_slice(&wrap_nonce);
+    out.extend_from_slice(&wrapped_dek);
+    Ok(out)
+}
+
+fn unwrap_dek(keyring: &Keyring, sealed: &[u8]) -> Result<Key<Aes256Gcm>> {
+    if !is_sealed(sealed) {
+        return Err(Error::Storage("Data is not sealed".to_string()));
+    }
+
+    let mut offset = MAGIC.len() + 1;
+    let kid = &sealed[offset..offset + KID_LEN];
+    offset += KID_LEN;
+    let wrap_nonce = Nonce::from_slice(&sealed[offset..offset + NONCE_LEN]);
+    offset += NONCE_LEN;
+    let wrapped_dek = &sealed[offset..offset + WRAPPED_DEK_LEN];
+
+    let kek = keyring.get(kid).ok_or(Error::Storage(
+        "Unknown master key, was it removed from MASTER_KEY?".to_string(),
+    ))?;
+    let dek = kek
+        .cipher
+        .decrypt(
+            wrap_nonce,
+            Payload {
+                msg: wrapped_dek,
+                aad: kid,
+            },
+        )
+        .map_err(|_| Error::Storage("Failed to unwrap data key".to_string()))?;
+
+    Ok(*Key::<Aes256Gcm>::from_slice(&dek))
+}
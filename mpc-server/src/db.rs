@@ -1,39 +1,125 @@
-use rusqlite::{Connection, Error, params_from_iter};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
 use std::collections::HashSet;
 
-use crate::matching::DATA_DIR;
+use crate::config::config;
+use crate::contacts::{Channel, Contact, Visibility};
+use crate::crypto;
+use crate::error::{AuthError, Error, Result};
 
 #[derive(Debug, Clone)]
 pub struct User {
     pub id: String,
-    #[allow(dead_code)]
-    pub twitter_handle: String,
     pub checked: Vec<String>,
+    /// `profile::CIRCUIT_ABI_VERSION` the shares were split for
+    pub abi_version: u32,
+    /// Lowest compatibility score for a match to be revealed
+    pub min_score: u32,
+    /// Version of the shares used for matching, see `shares::share_path`
+    pub share_version: u32,
+    /// Suspended by a moderator: not matched anymore, and their matches are hidden
+    pub suspended: bool,
 }
 
+/// An encrypted chat message, the server can't read it
 #[derive(Debug, Clone)]
-pub struct Match {
-    #[allow(dead_code)]
-    pub id: u32,
-    pub user_id1: String,
-    pub user_id2: String,
+pub struct MessageRow {
+    pub id: i64,
+    pub sender_id: String,
+    pub ciphertext: String,
+    pub created_at: u64,
 }
 
-pub fn connect_db() -> Result<Connection, Box<dyn std::error::Error + Send + Sync>> {
-    let conn = Connection::open(DATA_DIR.join("db.sqlite"))?;
+/// Something that happened to the user, without saying who caused it
+#[derive(Debug, Clone, Serialize)]
+pub struct Notice {
+    pub kind: String,
+    pub created_at: u64,
+}
+
+/// Shares uploaded by a user. Previous versions are kept on disk to roll back to.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareVersion {
+    pub version: u32,
+    pub abi_version: u32,
+    pub created_at: u64,
+}
+
+/// A report in the moderation queue, see `moderation.rs`
+#[derive(Debug, Clone)]
+pub struct ReportRow {
+    pub id: i64,
+    pub match_id: i64,
+    pub reporter_id: String,
+    pub reported_id: String,
+    pub reason: String,
+    pub details: Option<String>,
+    pub status: String,
+    pub created_at: u64,
+    pub reviewed_at: Option<u64>,
+    /// The reported user deleted their account
+    pub reported_deleted: bool,
+}
+
+/// A match whose score reaches the threshold of both users, see `reveal.rs`
+#[derive(Debug, Clone)]
+pub struct MatchRow {
+    pub id: i64,
+    /// The user is `user_id1` of the match
+    pub is_user1: bool,
+    pub other_id: String,
+    pub score: u32,
+    pub state: String,
+    pub created_at: u64,
+}
+
+pub fn connect_db() -> Result<Connection> {
+    let conn = Connection::open(config().data_dir.join("db.sqlite"))?;
     Ok(conn)
 }
 
-pub fn setup_db() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub fn setup_db() -> Result<()> {
     let conn = connect_db()?;
     conn.execute(
         "CREATE TABLE IF NOT EXISTS users (
             id                  TEXT NOT NULL UNIQUE,
-            twitter_handle      TEXT NOT NULL,
             checked             TEXT NOT NULL DEFAULT '[]'
         )",
         (), // empty list of parameters.
     )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS contacts (
+            id          INTEGER PRIMARY KEY,
+            user_id     TEXT NOT NULL,
+            channel     TEXT NOT NULL,
+            value       TEXT NOT NULL,
+            visibility  TEXT NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        )",
+        (),
+    )?;
+    migrate_twitter_handles(&conn)?;
+    // users from before the ABI was versioned have shares for the first one
+    add_column(&conn, "users", "abi_version", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column(&conn, "users", "min_score", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column(
+        &conn,
+        "users",
+        "share_version",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column(&conn, "users", "suspended", "INTEGER NOT NULL DEFAULT 0")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS share_versions (
+            user_id     TEXT NOT NULL,
+            version     INTEGER NOT NULL,
+            abi_version INTEGER NOT NULL,
+            created_at  INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            PRIMARY KEY (user_id, version)
+        )",
+        (),
+    )?;
     conn.execute(
         "CREATE TABLE IF NOT EXISTS matches (
             id          INTEGER PRIMARY KEY,
@@ -45,72 +131,374 @@ pub fn setup_db() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         )",
         (),
     )?;
+    // matches from before scoring needed at least one interest in common
+    add_column(&conn, "matches", "score", "INTEGER NOT NULL DEFAULT 1")?;
+    // matches from before the reveal needed both users to accept were already revealed
+    add_column(
+        &conn,
+        "matches",
+        "state",
+        "TEXT NOT NULL DEFAULT 'revealed'",
+    )?;
+    add_column(&conn, "matches", "created_at", "INTEGER")?;
+    // matches from before they could expire start their TTL now
+    conn.execute(
+        "UPDATE matches SET created_at = strftime('%s', 'now') WHERE created_at IS NULL",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chat_keys (
+            match_id    INTEGER NOT NULL,
+            user_id     TEXT NOT NULL,
+            public_key  TEXT NOT NULL,
+            FOREIGN KEY (match_id) REFERENCES matches(id),
+            PRIMARY KEY (match_id, user_id)
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS messages (
+            id          INTEGER PRIMARY KEY,
+            match_id    INTEGER NOT NULL,
+            sender_id   TEXT NOT NULL,
+            ciphertext  TEXT NOT NULL,
+            created_at  INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY (match_id) REFERENCES matches(id)
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS messages_match ON messages (match_id, id)",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS blocks (
+            user_id     TEXT NOT NULL,
+            blocked_id  TEXT NOT NULL,
+            created_at  INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (blocked_id) REFERENCES users(id),
+            PRIMARY KEY (user_id, blocked_id)
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS reports (
+            id          INTEGER PRIMARY KEY,
+            match_id    INTEGER NOT NULL,
+            reporter_id TEXT NOT NULL,
+            reported_id TEXT NOT NULL,
+            reason      TEXT NOT NULL,
+            details     TEXT,
+            status      TEXT NOT NULL DEFAULT 'open',
+            created_at  INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            reviewed_at INTEGER,
+            FOREIGN KEY (reporter_id) REFERENCES users(id),
+            FOREIGN KEY (reported_id) REFERENCES users(id)
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS refresh_tokens (
+            token_hash  TEXT PRIMARY KEY,
+            user_id     TEXT NOT NULL,
+            expires_at  INTEGER NOT NULL,
+            revoked     INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS recovery_codes (
+            user_id     TEXT PRIMARY KEY,
+            code_hash   TEXT NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS notices (
+            id          INTEGER PRIMARY KEY,
+            user_id     TEXT NOT NULL,
+            kind        TEXT NOT NULL,
+            created_at  INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS revoked_tokens (
+            jti         TEXT PRIMARY KEY,
+            expires_at  INTEGER NOT NULL
+        )",
+        (),
+    )?;
 
     Ok(())
 }
 
-pub fn insert_user(
-    conn: &Connection,
-    id: &str,
-    twitter_handle: &str,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if twitter_handle.is_empty() || twitter_handle.len() > 30 {
-        return Err("Invalid twitter handle".into());
+/// Moves the handles of the `twitter_handle` column, from before contacts had channels, to
+/// `contacts`
+fn migrate_twitter_handles(conn: &Connection) -> Result<()> {
+    let exists = conn
+        .prepare("SELECT 1 FROM pragma_table_info('users') WHERE name = 'twitter_handle'")?
+        .exists([])?;
+    if !exists {
+        return Ok(());
+    }
+
+    let rows = {
+        let mut stmt = conn.prepare("SELECT id, twitter_handle FROM users")?;
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?
+    };
+    let tx = conn.unchecked_transaction()?;
+    for (id, handle) in rows {
+        // handles can still be in plaintext, see `reencrypt_contacts`
+        let handle = match hex::decode(&handle) {
+            Ok(bin) if crypto::is_sealed(&bin) => crypto::open_str(&handle)?,
+            _ => handle,
+        };
+        if !handle.is_empty() {
+            tx.execute(
+                "INSERT INTO contacts (user_id, channel, value, visibility)
+                VALUES (?1, 'twitter', ?2, 'matches')",
+                (id, crypto::seal_str(&handle)?),
+            )?;
+        }
+    }
+    tx.execute("ALTER TABLE users DROP COLUMN twitter_handle", ())?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Adds a column to a table created by an older version of the server
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?1"
+        ))?
+        .exists([column])?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            (),
+        )?;
     }
+    Ok(())
+}
 
+pub fn insert_user(conn: &Connection, id: &str, abi_version: u32) -> Result<()> {
     let checked = serde_json::to_string(&vec![id])?;
 
     conn.execute(
-        "INSERT INTO users (id, twitter_handle, checked) VALUES (?1, ?2, ?3)",
-        (id, twitter_handle, checked),
+        "INSERT INTO users (id, checked, abi_version) VALUES (?1, ?2, ?3)",
+        (id, checked, abi_version),
+    )?;
+    conn.execute(
+        "INSERT INTO share_versions (user_id, version, abi_version) VALUES (?1, 0, ?2)",
+        (id, abi_version),
     )?;
 
     Ok(())
 }
 
-pub fn get_user(
-    conn: &Connection,
+/// Takes the next share version of the user. The insert is a single statement, so two uploads
+/// at once can't get the same version and write over each other's files.
+pub fn reserve_share_version(conn: &Connection, user_id: &str, abi_version: u32) -> Result<u32> {
+    Ok(conn.query_row(
+        "INSERT INTO share_versions (user_id, version, abi_version)
+        SELECT ?1, COALESCE(MAX(version), 0) + 1, ?2 FROM share_versions WHERE user_id = ?1
+        RETURNING version",
+        (user_id, abi_version),
+        |row| row.get(0),
+    )?)
+}
+
+/// Gives back a reserved version whose files couldn't be written
+pub fn delete_share_version(conn: &Connection, user_id: &str, version: u32) -> Result<()> {
+    conn.execute(
+        "DELETE FROM share_versions WHERE user_id = ?1 AND version = ?2",
+        (user_id, version),
+    )?;
+    Ok(())
+}
+
+/// Makes `version` the shares of the user, and forgets every pair they were evaluated in so they
+/// are matched again. Matches nobody can act on anymore are kept, pending and half accepted ones
+/// are dropped: their score came from the other shares, and they are found again with the new
+/// score if the pair still passes.
+pub fn set_share_version(
+    conn: &mut Connection,
     user_id: &str,
-) -> Result<User, Box<dyn std::error::Error + Send + Sync>> {
-    let mut stmt = conn.prepare("SELECT * FROM users WHERE id = ?1")?;
-    let user = stmt.query_row([user_id], |row| {
-        let checked: String = row.get(2)?;
-        Ok(User {
-            id: row.get(0)?,
-            twitter_handle: row.get(1)?,
-            checked: serde_json::from_str(&checked).map_err(|_| rusqlite::Error::InvalidQuery)?,
+    version: u32,
+    abi_version: u32,
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT OR IGNORE INTO share_versions (user_id, version, abi_version) VALUES (?1, ?2, ?3)",
+        (user_id, version, abi_version),
+    )?;
+    tx.execute(
+        "UPDATE users SET share_version = ?1, abi_version = ?2, checked = ?3 WHERE id = ?4",
+        (
+            version,
+            abi_version,
+            serde_json::to_string(&vec![user_id])?,
+            user_id,
+        ),
+    )?;
+    tx.execute(
+        "DELETE FROM matches WHERE (user_id1 = ?1 OR user_id2 = ?1)
+            AND state IN ('pending', 'accepted_by_user1', 'accepted_by_user2')",
+        [user_id],
+    )?;
+    uncheck(&tx, user_id)?;
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// Removes the user from the `checked` list of every other user
+fn uncheck(conn: &Connection, user_id: &str) -> Result<()> {
+    let others = {
+        let mut stmt = conn.prepare("SELECT id, checked FROM users WHERE id != ?1")?;
+        stmt.query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?
+    };
+    for (id, checked) in others {
+        let checked: Vec<String> = serde_json::from_str(&checked)?;
+        if checked.iter().any(|c| c == user_id) {
+            let checked: Vec<&String> = checked.iter().filter(|c| *c != user_id).collect();
+            conn.execute(
+                "UPDATE users SET checked = ?1 WHERE id = ?2",
+                (serde_json::to_string(&checked)?, id),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes every row about the user. Returns the users who could see a match with them, and the
+/// number of match rows deleted, whatever their state.
+pub fn delete_user(conn: &mut Connection, user_id: &str) -> Result<(Vec<String>, usize)> {
+    let tx = conn.transaction()?;
+    let counterparts = get_matches(&tx, user_id)?
+        .into_iter()
+        .map(|m| m.other_id)
+        .collect();
+
+    for table in ["messages", "chat_keys"] {
+        tx.execute(
+            &format!(
+                "DELETE FROM {table} WHERE match_id IN
+                (SELECT id FROM matches WHERE user_id1 = ?1 OR user_id2 = ?1)"
+            ),
+            [user_id],
+        )?;
+    }
+    let matches = tx.execute(
+        "DELETE FROM matches WHERE user_id1 = ?1 OR user_id2 = ?1",
+        [user_id],
+    )?;
+    // reports and blocks against the user are kept for the moderators
+    tx.execute("DELETE FROM blocks WHERE user_id = ?1", [user_id])?;
+    for table in [
+        "share_versions",
+        "contacts",
+        "refresh_tokens",
+        "recovery_codes",
+        "notices",
+    ] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE user_id = ?1"),
+            [user_id],
+        )?;
+    }
+    if tx.execute("DELETE FROM users WHERE id = ?1", [user_id])? == 0 {
+        return Err(Error::NotFound("User"));
+    }
+    uncheck(&tx, user_id)?;
+
+    tx.commit()?;
+    Ok((counterparts, matches))
+}
+
+pub fn insert_notices(conn: &Connection, user_ids: &[String], kind: &str) -> Result<()> {
+    let mut stmt = conn.prepare("INSERT INTO notices (user_id, kind) VALUES (?1, ?2)")?;
+    for user_id in user_ids {
+        stmt.execute((user_id, kind))?;
+    }
+    Ok(())
+}
+
+pub fn get_notices(conn: &Connection, user_id: &str) -> Result<Vec<Notice>> {
+    let mut stmt =
+        conn.prepare("SELECT kind, created_at FROM notices WHERE user_id = ?1 ORDER BY id DESC")?;
+    let notices = stmt.query_map([user_id], |row| {
+        Ok(Notice {
+            kind: row.get(0)?,
+            created_at: row.get(1)?,
         })
     })?;
-
-    Ok(user)
+    Ok(notices.collect::<Result<Vec<_>, rusqlite::Error>>()?)
 }
 
-pub fn get_all_users(
-    conn: &Connection,
-) -> Result<Vec<User>, Box<dyn std::error::Error + Send + Sync>> {
-    let mut stmt = conn.prepare("SELECT * FROM users")?;
-    let users = stmt.query_map([], |row| {
-        let checked: String = row.get(2)?;
-        Ok(User {
-            id: row.get(0)?,
-            twitter_handle: row.get(1)?,
-            checked: serde_json::from_str(&checked).map_err(|_| rusqlite::Error::InvalidQuery)?,
+pub fn get_share_versions(conn: &Connection, user_id: &str) -> Result<Vec<ShareVersion>> {
+    let mut stmt = conn.prepare(
+        "SELECT version, abi_version, created_at FROM share_versions
+        WHERE user_id = ?1 ORDER BY version",
+    )?;
+    let versions = stmt.query_map([user_id], |row| {
+        Ok(ShareVersion {
+            version: row.get(0)?,
+            abi_version: row.get(1)?,
+            created_at: row.get(2)?,
         })
     })?;
+    Ok(versions.collect::<Result<Vec<_>, rusqlite::Error>>()?)
+}
+
+pub fn get_user(conn: &Connection, user_id: &str) -> Result<User> {
+    let mut stmt = conn.prepare(&format!("SELECT {USER_COLUMNS} FROM users WHERE id = ?1"))?;
+    let user = stmt.query_row([user_id], user_from_row);
 
-    Ok(users.collect::<Result<Vec<User>, Error>>()?)
+    match user {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Error::NotFound("User")),
+        user => Ok(user?),
+    }
 }
 
-pub fn update_checked(
-    conn: &Connection,
-    user_id: &str,
-    new_checked: Vec<String>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Users whose shares were split for `abi_version`, the only ones that can be matched together.
+/// Suspended users are left out.
+pub fn get_all_users(conn: &Connection, abi_version: u32) -> Result<Vec<User>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {USER_COLUMNS} FROM users WHERE abi_version = ?1 AND suspended = 0"
+    ))?;
+    let users = stmt.query_map([abi_version], user_from_row)?;
+
+    Ok(users.collect::<Result<Vec<User>, rusqlite::Error>>()?)
+}
+
+const USER_COLUMNS: &str = "id, checked, abi_version, min_score, share_version, suspended";
+
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    let checked: String = row.get(1)?;
+    Ok(User {
+        id: row.get(0)?,
+        checked: serde_json::from_str(&checked).map_err(|_| rusqlite::Error::InvalidQuery)?,
+        abi_version: row.get(2)?,
+        min_score: row.get(3)?,
+        share_version: row.get(4)?,
+        suspended: row.get(5)?,
+    })
+}
+
+pub fn update_checked(conn: &Connection, user_id: &str, new_checked: Vec<String>) -> Result<()> {
     let user = get_user(conn, user_id)?;
 
     let mut checked: HashSet<String> = user.checked.into_iter().collect();
     checked.extend(new_checked);
-    println!("checked: {:?}", checked);
 
     conn.execute(
         "UPDATE users SET checked = ?1 WHERE id = ?2",
@@ -123,7 +511,7 @@ pub fn update_checked_many(
     conn: &Connection,
     user_ids: Vec<String>,
     new_checked: Vec<String>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<()> {
     let mut stmt = conn.prepare("UPDATE users SET checked = ?1 WHERE id = ?2")?;
 
     for user_id in user_ids {
@@ -135,62 +523,413 @@ pub fn update_checked_many(
     Ok(())
 }
 
-pub fn insert_matches(
+pub fn set_min_score(conn: &Connection, user_id: &str, min_score: u32) -> Result<()> {
+    conn.execute(
+        "UPDATE users SET min_score = ?1 WHERE id = ?2",
+        (min_score, user_id),
+    )?;
+    Ok(())
+}
+
+/// Records the pairs which passed the hard filters, with their score.
+/// A pair evaluated again after a re-upload keeps its match and its state, with the new score:
+/// unmatched and expired matches stay so.
+pub fn insert_matches(conn: &Connection, matches: Vec<(String, String, u32)>) -> Result<()> {
+    let mut update = conn.prepare(
+        "UPDATE matches SET score = ?3
+        WHERE (user_id1 = ?1 AND user_id2 = ?2) OR (user_id1 = ?2 AND user_id2 = ?1)",
+    )?;
+    let mut insert = conn.prepare(
+        "INSERT INTO matches (user_id1, user_id2, score, state, created_at)
+        VALUES (?1, ?2, ?3, 'pending', strftime('%s', 'now'))",
+    )?;
+    for (user_id1, user_id2, score) in matches {
+        if update.execute((&user_id1, &user_id2, score))? == 0 {
+            insert.execute((user_id1, user_id2, score))?;
+        }
+    }
+    Ok(())
+}
+
+const MATCH_QUERY: &str =
+    "SELECT m.id, m.user_id1 = ?1, IIF(m.user_id1 = ?1, m.user_id2, m.user_id1), m.score, m.state,
+        m.created_at
+    FROM matches m
+    JOIN users u1 ON u1.id = m.user_id1
+    JOIN users u2 ON u2.id = m.user_id2
+    WHERE (m.user_id1 = ?1 OR m.user_id2 = ?1)
+        AND m.score >= u1.min_score AND m.score >= u2.min_score
+        AND NOT u1.suspended AND NOT u2.suspended
+        AND NOT EXISTS (SELECT 1 FROM blocks b
+            WHERE (b.user_id = m.user_id1 AND b.blocked_id = m.user_id2)
+                OR (b.user_id = m.user_id2 AND b.blocked_id = m.user_id1))";
+
+fn match_from_row(row: &rusqlite::Row) -> rusqlite::Result<MatchRow> {
+    Ok(MatchRow {
+        id: row.get(0)?,
+        is_user1: row.get(1)?,
+        other_id: row.get(2)?,
+        score: row.get(3)?,
+        state: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+/// Matches of the user whose score reaches the threshold of both users, best first.
+/// Blocked pairs and suspended users are left out.
+pub fn get_matches(conn: &Connection, user_id: &str) -> Result<Vec<MatchRow>> {
+    let mut stmt = conn.prepare(&format!("{MATCH_QUERY} ORDER BY m.score DESC"))?;
+    let matches = stmt.query_map([user_id], match_from_row)?;
+    Ok(matches.collect::<Result<Vec<_>, rusqlite::Error>>()?)
+}
+
+/// A match of the user, if they can see it
+pub fn get_match(conn: &Connection, user_id: &str, match_id: i64) -> Result<MatchRow> {
+    let mut stmt = conn.prepare(&format!("{MATCH_QUERY} AND m.id = ?2"))?;
+    match stmt.query_row((user_id, match_id), match_from_row) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Error::NotFound("Match")),
+        m => Ok(m?),
+    }
+}
+
+/// Only updates the state if it is still `from`, returns whether it was updated
+pub fn update_match_state(conn: &Connection, match_id: i64, from: &str, to: &str) -> Result<bool> {
+    let updated = conn.execute(
+        "UPDATE matches SET state = ?1 WHERE id = ?2 AND state = ?3",
+        (to, match_id, from),
+    )?;
+    Ok(updated == 1)
+}
+
+/// Expires the matches created before `created_before` that aren't accepted by both users yet.
+/// Returns how many expired.
+pub fn expire_matches(conn: &Connection, created_before: u64) -> Result<usize> {
+    Ok(conn.execute(
+        "UPDATE matches SET state = 'expired'
+        WHERE state IN ('pending', 'accepted_by_user1', 'accepted_by_user2')
+            AND created_at < ?1",
+        [created_before],
+    )?)
+}
+
+pub fn insert_block(conn: &Connection, user_id: &str, blocked_id: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO blocks (user_id, blocked_id) VALUES (?1, ?2)",
+        (user_id, blocked_id),
+    )?;
+    Ok(())
+}
+
+/// Users the user blocked or was blocked by
+pub fn get_blocked(conn: &Connection, user_id: &str) -> Result<HashSet<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT blocked_id FROM blocks WHERE user_id = ?1
+        UNION SELECT user_id FROM blocks WHERE blocked_id = ?1",
+    )?;
+    let blocked = stmt.query_map([user_id], |row| row.get(0))?;
+    Ok(blocked.collect::<Result<HashSet<String>, rusqlite::Error>>()?)
+}
+
+pub fn set_suspended(conn: &Connection, user_id: &str) -> Result<()> {
+    conn.execute("UPDATE users SET suspended = 1 WHERE id = ?1", [user_id])?;
+    Ok(())
+}
+
+/// The details are sealed, like the contacts
+pub fn insert_report(
     conn: &Connection,
-    matches: Vec<(String, String)>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut stmt = conn.prepare("INSERT INTO matches (user_id1, user_id2) VALUES (?1, ?2)")?;
-    for (user_id1, user_id2) in matches {
-        stmt.execute((user_id1, user_id2))?;
+    match_id: i64,
+    reporter_id: &str,
+    reported_id: &str,
+    reason: &str,
+    details: Option<&str>,
+) -> Result<i64> {
+    let details = details.map(crypto::seal_str).transpose()?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO reports (match_id, reporter_id, reported_id, reason, details)
+        VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
+    )?;
+    Ok(stmt.query_row(
+        (match_id, reporter_id, reported_id, reason, details),
+        |row| row.get(0),
+    )?)
+}
+
+const REPORT_COLUMNS: &str = "id, match_id, reporter_id, reported_id, reason, details, status,
+    created_at, reviewed_at, reported_id NOT IN (SELECT id FROM users)";
+
+fn report_from_rows(mut rows: rusqlite::Rows) -> Result<Vec<ReportRow>> {
+    let mut reports = Vec::new();
+    while let Some(row) = rows.next()? {
+        let details: Option<String> = row.get(5)?;
+        reports.push(ReportRow {
+            id: row.get(0)?,
+            match_id: row.get(1)?,
+            reporter_id: row.get(2)?,
+            reported_id: row.get(3)?,
+            reason: row.get(4)?,
+            details: details.as_deref().map(crypto::open_str).transpose()?,
+            status: row.get(6)?,
+            created_at: row.get(7)?,
+            reviewed_at: row.get(8)?,
+            reported_deleted: row.get(9)?,
+        });
     }
+    Ok(reports)
+}
+
+/// Reports with this status, oldest first
+pub fn get_reports(conn: &Connection, status: &str) -> Result<Vec<ReportRow>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {REPORT_COLUMNS} FROM reports WHERE status = ?1 ORDER BY id"
+    ))?;
+    report_from_rows(stmt.query([status])?)
+}
+
+pub fn get_report(conn: &Connection, id: i64) -> Result<ReportRow> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {REPORT_COLUMNS} FROM reports WHERE id = ?1"
+    ))?;
+    report_from_rows(stmt.query([id])?)?
+        .pop()
+        .ok_or(Error::NotFound("Report"))
+}
+
+/// Reports filed against the user, whatever their status
+pub fn count_reports(conn: &Connection, reported_id: &str) -> Result<u32> {
+    Ok(conn.query_row(
+        "SELECT COUNT(*) FROM reports WHERE reported_id = ?1",
+        [reported_id],
+        |row| row.get(0),
+    )?)
+}
+
+/// Only closes the report if it is still open, returns whether it was
+pub fn close_report(conn: &Connection, id: i64, status: &str, reviewed_at: u64) -> Result<bool> {
+    let updated = conn.execute(
+        "UPDATE reports SET status = ?1, reviewed_at = ?2 WHERE id = ?3 AND status = 'open'",
+        (status, reviewed_at, id),
+    )?;
+    Ok(updated == 1)
+}
+
+pub fn set_chat_key(
+    conn: &Connection,
+    match_id: i64,
+    user_id: &str,
+    public_key: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO chat_keys (match_id, user_id, public_key) VALUES (?1, ?2, ?3)
+        ON CONFLICT(match_id, user_id) DO UPDATE SET public_key = excluded.public_key",
+        (match_id, user_id, public_key),
+    )?;
     Ok(())
 }
 
-pub fn get_matches(
-    user_id: String,
-) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
-    let conn = connect_db()?;
-    let mut stmt = conn.prepare("SELECT * FROM matches WHERE (user_id1 = ?1 OR user_id2 = ?1)")?;
-    let matches = stmt.query_map([user_id.clone()], |row| {
-        Ok(Match {
+pub fn get_chat_key(conn: &Connection, match_id: i64, user_id: &str) -> Result<Option<String>> {
+    let mut stmt =
+        conn.prepare("SELECT public_key FROM chat_keys WHERE match_id = ?1 AND user_id = ?2")?;
+    match stmt.query_row((match_id, user_id), |row| row.get(0)) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        key => Ok(Some(key?)),
+    }
+}
+
+pub fn insert_message(
+    conn: &Connection,
+    match_id: i64,
+    sender_id: &str,
+    ciphertext: &str,
+) -> Result<(i64, u64)> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO messages (match_id, sender_id, ciphertext) VALUES (?1, ?2, ?3)
+        RETURNING id, created_at",
+    )?;
+    Ok(stmt.query_row((match_id, sender_id, ciphertext), |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })?)
+}
+
+/// Messages of a match older than `before`, newest first
+pub fn get_messages(
+    conn: &Connection,
+    match_id: i64,
+    before: Option<i64>,
+    limit: u32,
+) -> Result<Vec<MessageRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, sender_id, ciphertext, created_at FROM messages
+        WHERE match_id = ?1 AND id < ?2
+        ORDER BY id DESC LIMIT ?3",
+    )?;
+    let messages = stmt.query_map((match_id, before.unwrap_or(i64::MAX), limit), |row| {
+        Ok(MessageRow {
             id: row.get(0)?,
-            user_id1: row.get(1)?,
-            user_id2: row.get(2)?,
+            sender_id: row.get(1)?,
+            ciphertext: row.get(2)?,
+            created_at: row.get(3)?,
         })
     })?;
+    Ok(messages.collect::<Result<Vec<_>, rusqlite::Error>>()?)
+}
 
-    let matches = matches
-        .into_iter()
-        .map(|m| m)
-        .collect::<Result<Vec<Match>, Error>>()?
-        .iter()
-        .map(|m| {
-            if m.user_id1 == user_id.clone() {
-                m.user_id2.clone()
-            } else {
-                m.user_id1.clone()
-            }
+pub fn get_contacts(conn: &Connection, user_id: &str) -> Result<Vec<Contact>> {
+    let mut stmt = conn.prepare(
+        "SELECT channel, value, visibility FROM contacts WHERE user_id = ?1 ORDER BY id",
+    )?;
+    let rows = stmt
+        .query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<(Channel, String, Visibility)>, rusqlite::Error>>()?;
+    rows.into_iter()
+        .map(|(channel, value, visibility)| {
+            Ok(Contact {
+                channel,
+                value: crypto::open_str(&value)?,
+                visibility,
+            })
         })
-        .collect::<Vec<String>>();
+        .collect()
+}
 
-    if matches.is_empty() {
-        return Ok(vec![]);
+pub fn insert_contacts(conn: &Connection, user_id: &str, contacts: &[Contact]) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO contacts (user_id, channel, value, visibility) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for contact in contacts {
+        stmt.execute((
+            user_id,
+            contact.channel,
+            crypto::seal_str(&contact.value)?,
+            contact.visibility,
+        ))?;
     }
+    Ok(())
+}
+
+pub fn replace_contacts(conn: &mut Connection, user_id: &str, contacts: &[Contact]) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM contacts WHERE user_id = ?1", [user_id])?;
+    insert_contacts(&tx, user_id, contacts)?;
+    tx.commit()?;
+    Ok(())
+}
 
-    fn repeat_vars(count: usize) -> String {
-        assert_ne!(count, 0);
-        let mut s = "?,".repeat(count);
-        // Remove trailing comma
-        s.pop();
-        s
+/// Re-seals every contact with the current master key.
+/// Contacts stored in plaintext (twitter handles from before encryption at rest was added) are
+/// sealed.
+pub fn reencrypt_contacts(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT id, value FROM contacts")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(i64, String)>, rusqlite::Error>>()?;
+
+    let mut update = conn.prepare("UPDATE contacts SET value = ?1 WHERE id = ?2")?;
+    for (id, value) in &rows {
+        let sealed = match hex::decode(value) {
+            Ok(bin) if crypto::is_sealed(&bin) => hex::encode(crypto::rewrap(&bin)?),
+            _ => crypto::seal_str(value)?,
+        };
+        update.execute((sealed, id))?;
     }
 
-    let mut stmt = conn.prepare(&format!(
-        "SELECT twitter_handle FROM users WHERE id IN ({})",
-        repeat_vars(matches.iter().count())
-    ))?;
-    let user_matches = stmt.query_map(params_from_iter(matches.iter()), |row| row.get(0))?;
-    let user_matches = user_matches.collect::<Result<Vec<String>, Error>>()?;
+    Ok(rows.len())
+}
+
+pub fn insert_refresh_token(
+    conn: &Connection,
+    token_hash: &str,
+    user_id: &str,
+    expires_at: u64,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO refresh_tokens (token_hash, user_id, expires_at) VALUES (?1, ?2, ?3)",
+        (token_hash, user_id, expires_at),
+    )?;
+    Ok(())
+}
+
+/// Revokes a refresh token and returns the user it belongs to.
+/// Presenting an already revoked token revokes every refresh token of the user,
+/// since it means the token was stolen or replayed.
+pub fn consume_refresh_token(conn: &Connection, token_hash: &str, now: u64) -> Result<String> {
+    // a single statement, so two requests can't both consume the token
+    let consumed: Option<String> = conn
+        .query_row(
+            "UPDATE refresh_tokens SET revoked = 1
+            WHERE token_hash = ?1 AND revoked = 0 AND expires_at >= ?2
+            RETURNING user_id",
+            (token_hash, now),
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(user_id) = consumed {
+        return Ok(user_id);
+    }
+
+    let revoked_owner: Option<String> = conn
+        .query_row(
+            "SELECT user_id FROM refresh_tokens WHERE token_hash = ?1 AND revoked = 1",
+            [token_hash],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(user_id) = revoked_owner {
+        revoke_refresh_tokens(conn, &user_id)?;
+    }
+    Err(AuthError::InvalidRefreshToken.into())
+}
+
+pub fn revoke_refresh_tokens(conn: &Connection, user_id: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE refresh_tokens SET revoked = 1 WHERE user_id = ?1",
+        [user_id],
+    )?;
+    Ok(())
+}
+
+/// Returns whether the token wasn't revoked yet
+pub fn revoke_token(conn: &Connection, jti: &str, expires_at: u64) -> Result<bool> {
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO revoked_tokens (jti, expires_at) VALUES (?1, ?2)",
+        (jti, expires_at),
+    )?;
+    Ok(inserted == 1)
+}
+
+pub fn user_exists(conn: &Connection, user_id: &str) -> Result<bool> {
+    let mut stmt = conn.prepare("SELECT 1 FROM users WHERE id = ?1")?;
+    Ok(stmt.exists([user_id])?)
+}
+
+pub fn is_token_revoked(conn: &Connection, jti: &str) -> Result<bool> {
+    let mut stmt = conn.prepare("SELECT 1 FROM revoked_tokens WHERE jti = ?1")?;
+    Ok(stmt.exists([jti])?)
+}
+
+/// Removes revoked and refresh tokens which are expired anyway.
+pub fn purge_expired_tokens(conn: &Connection, now: u64) -> Result<()> {
+    conn.execute("DELETE FROM revoked_tokens WHERE expires_at < ?1", [now])?;
+    conn.execute("DELETE FROM refresh_tokens WHERE expires_at < ?1", [now])?;
+    Ok(())
+}
+
+pub fn set_recovery_code(conn: &Connection, user_id: &str, code_hash: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO recovery_codes (user_id, code_hash) VALUES (?1, ?2)
+        ON CONFLICT(user_id) DO UPDATE SET code_hash = excluded.code_hash",
+        (user_id, code_hash),
+    )?;
+    Ok(())
+}
+
+pub fn delete_recovery_code(conn: &Connection, user_id: &str) -> Result<()> {
+    conn.execute("DELETE FROM recovery_codes WHERE user_id = ?1", [user_id])?;
+    Ok(())
+}
 
-    Ok(user_matches)
+pub fn check_recovery_code(conn: &Connection, user_id: &str, code_hash: &str) -> Result<bool> {
+    let mut stmt =
+        conn.prepare("SELECT 1 FROM recovery_codes WHERE user_id = ?1 AND code_hash = ?2")?;
+    Ok(stmt.exists([user_id, code_hash])?)
 }
@@ -0,0 +1,159 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Validation(String),
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("MPC network error: {0}")]
+    MpcNetwork(String),
+    /// The circuit assertions failed: the users don't pass each other's hard filters
+    #[error("No match")]
+    NoMatch,
+    #[error("Proof failed: {0}")]
+    Proof(String),
+    #[error("Storage error: {0}")]
+    Storage(String),
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Missing token")]
+    MissingToken,
+    #[error("Invalid token")]
+    InvalidToken,
+    #[error("Expired token")]
+    ExpiredToken,
+    #[error("Revoked token")]
+    RevokedToken,
+    #[error("Invalid refresh token")]
+    InvalidRefreshToken,
+    #[error("Invalid recovery code")]
+    InvalidRecoveryCode,
+}
+
+impl Error {
+    /// Stable error code, for the frontend to react on
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Validation(_) => "validation_error",
+            Error::Auth(AuthError::MissingToken) => "missing_token",
+            Error::Auth(AuthError::InvalidToken) => "invalid_token",
+            Error::Auth(AuthError::ExpiredToken) => "expired_token",
+            Error::Auth(AuthError::RevokedToken) => "revoked_token",
+            Error::Auth(AuthError::InvalidRefreshToken) => "invalid_refresh_token",
+            Error::Auth(AuthError::InvalidRecoveryCode) => "invalid_recovery_code",
+            Error::Forbidden(_) => "forbidden",
+            Error::NotFound(_) => "not_found",
+            Error::Conflict(_) => "conflict",
+            Error::MpcNetwork(_) => "mpc_network_error",
+            Error::NoMatch => "no_match",
+            Error::Proof(_) => "proof_failed",
+            Error::Storage(_) => "storage_error",
+            Error::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The error as sent to clients, `{"error", "code"}`. Server errors are logged and only get a
+    /// generic message, so internal details don't leak.
+    pub fn to_json(&self) -> serde_json::Value {
+        let message = if self.status().is_server_error() {
+            tracing::error!("{self}");
+            match self {
+                Error::MpcNetwork(_) => "MPC network error".to_string(),
+                Error::Proof(_) => "Proof failed".to_string(),
+                _ => "Internal error".to_string(),
+            }
+        } else {
+            self.to_string()
+        };
+        json!({
+            "error": message,
+            "code": self.code(),
+        })
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::Auth(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) | Error::NoMatch => StatusCode::CONFLICT,
+            Error::MpcNetwork(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Proof(_) | Error::Storage(_) | Error::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let body = Json(self.to_json());
+        (self.status(), body).into_response()
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        match err {
+            rusqlite::Error::QueryReturnedNoRows => Error::NotFound("Record"),
+            rusqlite::Error::SqliteFailure(e, _)
+                if e.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Error::Conflict("Record already exists".to_string())
+            }
+            err => Error::Storage(err.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => Error::NotFound("File"),
+            _ => Error::Storage(err.to_string()),
+        }
+    }
+}
+
+impl From<axum::extract::multipart::MultipartError> for Error {
+    fn from(err: axum::extract::multipart::MultipartError) -> Self {
+        Error::Validation(err.body_text())
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Error::Storage(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
@@ -0,0 +1,60 @@
+use crate::error::{Error, Result};
+
+// Geohash cells, to compare locations in the circuit without giving it coordinates.
+//
+// A cell of `PRECISION` characters is ~5km wide. The circuit compares cells with their last
+// characters dropped: ~40km for `nearby`, ~150km for `wide`.
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+/// Characters of the cells given to the circuit
+pub const PRECISION: usize = 5;
+const BITS: usize = PRECISION * 5;
+
+/// Cell of the coordinates, as `PRECISION * 5` bits
+pub fn encode(lat: f64, lon: f64) -> Result<u32> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(Error::Validation("Invalid coordinates".to_string()));
+    }
+
+    let mut lat_range = (-90.0, 90.0);
+    let mut lon_range = (-180.0, 180.0);
+    let mut bits = 0;
+    // bits alternate between longitude and latitude, starting with longitude
+    for i in 0..BITS {
+        let (range, value) = if i % 2 == 0 {
+            (&mut lon_range, lon)
+        } else {
+            (&mut lat_range, lat)
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        bits <<= 1;
+        if value >= mid {
+            bits |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+    }
+    Ok(bits)
+}
+
+/// Reads a geohash computed by the client. Only the first `PRECISION` characters are kept.
+pub fn parse(geohash: &str) -> Result<u32> {
+    if geohash.len() < PRECISION {
+        return Err(Error::Validation(format!(
+            "Geohash must have at least {PRECISION} characters"
+        )));
+    }
+    geohash.bytes().take(PRECISION).try_fold(0, |bits, c| {
+        let index = BASE32
+            .iter()
+            .position(|&b| b == c.to_ascii_lowercase())
+            .ok_or(Error::Validation("Invalid geohash".to_string()))?;
+        Ok((bits << 5) | index as u32)
+    })
+}
+
+/// The circuit input: the cell with an extra bit set, since 0 means no location
+pub fn circuit_cell(cell: u32) -> u32 {
+    cell | (1 << BITS)
+}
@@ -0,0 +1,250 @@
+use axum::http::StatusCode;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    certs,
+    config::config,
+    db::connect_db,
+    error::{Error, Result},
+    metrics::{ACTIVE_SESSIONS, PENDING_PAIRS},
+};
+
+// Health, readiness and diagnostics.
+//
+// `/healthz` only checks that the server can reach its database, `/readyz` checks everything a
+// match needs, and `/diagnostics` describes the loaded circuit and the current load.
+// The public endpoints only tell which checks failed, the details (paths, ports, certificate
+// errors) are in `/diagnostics`, behind the admin token.
+
+static CIRCUIT: OnceCell<CircuitInfo> = OnceCell::new();
+/// MPC ports whose last network setup failed, with when and the error, as reported by the parties
+static PORT_ERRORS: Lazy<Mutex<BTreeMap<u16, (Instant, String)>>> = Lazy::new(Default::default);
+/// A port that failed is reported until it works again, or for this long: a rarely used port
+/// shouldn't keep the server unready after a transient failure
+const PORT_ERROR_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitInfo {
+    /// sha256 of `circuit.json`
+    pub circuit_hash: String,
+    pub crs_size: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+/// A check as shown on the public endpoints, without its detail
+#[derive(Debug, Serialize)]
+pub struct CheckStatus {
+    name: &'static str,
+    ok: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    ok: bool,
+    checks: Vec<CheckStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostics {
+    #[serde(flatten)]
+    circuit: Option<CircuitInfo>,
+    crs_loaded: bool,
+    active_sessions: i64,
+    queue_depth: i64,
+    /// Parties whose certificate is about to expire, with the days left
+    expiring_certs: Vec<(usize, i64)>,
+    /// The readiness checks, with their details
+    checks: Vec<Check>,
+}
+
+/// Counts a match session (a `POST /matches` call) and its pairs, until dropped
+pub struct Session {
+    remaining: AtomicUsize,
+}
+
+impl Session {
+    pub fn start(pairs: usize) -> Self {
+        ACTIVE_SESSIONS.inc();
+        PENDING_PAIRS.add(pairs as i64);
+        Session {
+            remaining: AtomicUsize::new(pairs),
+        }
+    }
+
+    pub fn pair_done(&self) {
+        self.remaining.fetch_sub(1, Ordering::Relaxed);
+        PENDING_PAIRS.dec();
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        PENDING_PAIRS.sub(self.remaining.load(Ordering::Relaxed) as i64);
+        ACTIVE_SESSIONS.dec();
+    }
+}
+
+pub fn circuit_hash(path: &Path) -> Result<String> {
+    Ok(hex::encode(Sha256::digest(std::fs::read(path)?)))
+}
+
+/// Short hash of the loaded circuit, to tell circuit versions apart in traces
+pub fn circuit_version() -> &'static str {
+    CIRCUIT
+        .get()
+        .map(|c| &c.circuit_hash[..12])
+        .unwrap_or("unknown")
+}
+
+/// Records the circuit and CRS loaded at startup
+pub fn init(circuit: CircuitInfo) -> Result<()> {
+    CIRCUIT
+        .set(circuit)
+        .map_err(|_| Error::Internal("Circuit info already set".to_string()))
+}
+
+pub fn healthz() -> (StatusCode, Report) {
+    report(vec![check("database", check_db())])
+}
+
+pub fn readyz() -> (StatusCode, Report) {
+    report(readiness_checks())
+}
+
+fn readiness_checks() -> Vec<Check> {
+    vec![
+        check("database", check_db()),
+        check("crs", check_crs()),
+        check("circuit", check_circuit()),
+        check("party_certs", check_certs()),
+        check("mpc_ports", check_mpc_ports()),
+    ]
+}
+
+pub fn diagnostics() -> Diagnostics {
+    Diagnostics {
+        circuit: CIRCUIT.get().cloned(),
+        crs_loaded: CIRCUIT.get().is_some(),
+        active_sessions: ACTIVE_SESSIONS.get(),
+        queue_depth: PENDING_PAIRS.get(),
+        expiring_certs: certs::current()
+            .map(|certs| certs.expiring())
+            .unwrap_or_default(),
+        checks: readiness_checks(),
+    }
+}
+
+fn report(checks: Vec<Check>) -> (StatusCode, Report) {
+    let ok = checks.iter().all(|c| c.ok);
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let checks = checks
+        .into_iter()
+        .map(|c| CheckStatus {
+            name: c.name,
+            ok: c.ok,
+        })
+        .collect();
+    (status, Report { ok, checks })
+}
+
+/// `Ok(Some(_))` is a passing check with a warning
+fn check(name: &'static str, result: Result<Option<String>>) -> Check {
+    match result {
+        Ok(detail) => Check {
+            name,
+            ok: true,
+            detail,
+        },
+        Err(e) => {
+            tracing::warn!("{name} check failed: {e}");
+            Check {
+                name,
+                ok: false,
+                detail: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+fn check_db() -> Result<Option<String>> {
+    connect_db()?.query_row("SELECT 1", (), |_| Ok(()))?;
+    Ok(None)
+}
+
+fn check_crs() -> Result<Option<String>> {
+    match CIRCUIT.get() {
+        Some(_) => Ok(None),
+        None => Err(Error::Internal("CRS not loaded".to_string())),
+    }
+}
+
+/// The circuit on disk must be the one that was loaded
+fn check_circuit() -> Result<Option<String>> {
+    let loaded = CIRCUIT
+        .get()
+        .ok_or(Error::Internal("Circuit not loaded".to_string()))?;
+    let on_disk = circuit_hash(&config().data_dir.join("circuit.json"))?;
+    if on_disk != loaded.circuit_hash {
+        return Err(Error::Internal(
+            "circuit.json changed since startup, restart the server".to_string(),
+        ));
+    }
+    Ok(None)
+}
+
+fn check_certs() -> Result<Option<String>> {
+    let expiring = certs::current()?.expiring();
+    if let Some((party, _)) = expiring.iter().find(|(_, days_left)| *days_left < 0) {
+        return Err(Error::Internal(format!("cert{party}.der has expired")));
+    }
+    Ok((!expiring.is_empty()).then(|| {
+        expiring
+            .iter()
+            .map(|(party, days_left)| format!("cert{party}.der expires in {days_left} days"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }))
+}
+
+/// Records the outcome of a party's network setup on `port`
+pub fn record_network_setup(port: u16, error: Option<&Error>) {
+    let mut errors = PORT_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    match error {
+        Some(e) => errors.insert(port, (Instant::now(), e.to_string())),
+        None => errors.remove(&port),
+    };
+}
+
+/// The parties run in this process and only listen during a match, so the ports can't be probed
+/// without getting in the way of a match: report the ports whose last network setup failed
+/// recently.
+fn check_mpc_ports() -> Result<Option<String>> {
+    let mut errors = PORT_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    errors.retain(|_, (at, _)| at.elapsed() < PORT_ERROR_TTL);
+    match errors.iter().next() {
+        Some((port, (_, e))) => Err(Error::MpcNetwork(format!("Port {port} unavailable: {e}"))),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,184 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse,
+    },
+};
+use once_cell::sync::OnceCell;
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ED25519};
+use std::path::PathBuf;
+
+use crate::{
+    config::config,
+    error::{Error, Result},
+};
+
+// JWT signing keys.
+//
+// Keys are PKCS#8 PEM files in `JWT_KEYS_DIR` (default: `config/jwt`), the file stem being the
+// key id (`kid`). Every key in the directory is accepted for verification, and tokens are signed
+// with `JWT_SIGNING_KID`, or the last kid in alphabetical order if unset.
+//
+// To rotate: add a new key (`cargo run --bin gen_jwt_key`), sign with it, and only remove the
+// old one once all the tokens it signed have expired.
+//
+// `JWT_SECRET` is still supported (HS256, no kid), but it can't be published in the JWKS.
+
+const LEGACY_KID: &str = "hs256";
+
+static KEYS: OnceCell<Keys> = OnceCell::new();
+
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding: EncodingKey,
+    pub decoding: DecodingKey,
+    /// `None` for the symmetric legacy key
+    pub jwk: Option<Jwk>,
+}
+
+pub struct Keys {
+    keys: Vec<SigningKey>,
+    signing: usize,
+}
+
+impl Keys {
+    fn load() -> Result<Self> {
+        let dir = std::env::var("JWT_KEYS_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| config().config_dir.join("jwt"));
+
+        let mut keys = Vec::new();
+        if dir.exists() {
+            let mut paths = std::fs::read_dir(&dir)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.retain(|p| p.extension().is_some_and(|ext| ext == "pem"));
+            paths.sort();
+
+            for path in paths {
+                let kid = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .ok_or(Error::Internal(format!(
+                        "Invalid key file name {}",
+                        path.display()
+                    )))?
+                    .to_string();
+                keys.push(load_key(kid, &std::fs::read_to_string(&path)?)?);
+            }
+        }
+
+        if let Ok(secret) = std::env::var("JWT_SECRET") {
+            keys.push(SigningKey {
+                kid: LEGACY_KID.to_string(),
+                algorithm: Algorithm::HS256,
+                encoding: EncodingKey::from_secret(secret.as_ref()),
+                decoding: DecodingKey::from_secret(secret.as_ref()),
+                jwk: None,
+            });
+        }
+
+        if keys.is_empty() {
+            return Err(Error::Internal(format!(
+                "No JWT key found: add keys to {} or set JWT_SECRET",
+                dir.display()
+            )));
+        }
+
+        let signing = match std::env::var("JWT_SIGNING_KID") {
+            Ok(kid) => keys
+                .iter()
+                .position(|k| k.kid == kid)
+                .ok_or(Error::Internal(format!("JWT_SIGNING_KID {kid} not found")))?,
+            // the legacy key is always last, only sign with it if there is nothing else
+            Err(_) => keys
+                .iter()
+                .rposition(|k| k.jwk.is_some())
+                .unwrap_or(keys.len() - 1),
+        };
+
+        Ok(Keys { keys, signing })
+    }
+
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.keys[self.signing]
+    }
+
+    /// Tokens without a kid were signed with `JWT_SECRET`.
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&SigningKey> {
+        let kid = kid.unwrap_or(LEGACY_KID);
+        self.keys.iter().find(|k| k.kid == kid)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|k| k.jwk.clone()).collect(),
+        }
+    }
+}
+
+pub fn keys() -> Result<&'static Keys> {
+    KEYS.get_or_try_init(Keys::load)
+}
+
+fn load_key(kid: String, pem: &str) -> Result<SigningKey> {
+    let key_pair =
+        KeyPair::from_pem(pem).map_err(|e| Error::Internal(format!("Invalid key {kid}: {e}")))?;
+    let public_key = key_pair.public_key_raw();
+
+    let (algorithm, encoding, decoding, params) = if key_pair.algorithm() == &PKCS_ED25519 {
+        (
+            Algorithm::EdDSA,
+            EncodingKey::from_ed_pem(pem.as_bytes())?,
+            DecodingKey::from_ed_der(public_key),
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(public_key),
+            }),
+        )
+    } else if key_pair.algorithm() == &PKCS_ECDSA_P256_SHA256 {
+        // uncompressed point: 0x04 | x | y
+        let (x, y) = public_key[1..].split_at(32);
+        (
+            Algorithm::ES256,
+            EncodingKey::from_ec_pem(pem.as_bytes())?,
+            DecodingKey::from_ec_der(public_key),
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: EllipticCurve::P256,
+                x: URL_SAFE_NO_PAD.encode(x),
+                y: URL_SAFE_NO_PAD.encode(y),
+            }),
+        )
+    } else {
+        return Err(Error::Internal(format!(
+            "Unsupported key type for {kid}, use Ed25519 or P-256"
+        )));
+    };
+
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(match algorithm {
+                Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+                _ => KeyAlgorithm::ES256,
+            }),
+            key_id: Some(kid.clone()),
+            ..Default::default()
+        },
+        algorithm: params,
+    };
+
+    Ok(SigningKey {
+        kid,
+        algorithm,
+        encoding,
+        decoding,
+        jwk: Some(jwk),
+    })
+}
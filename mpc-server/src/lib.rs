@@ -0,0 +1,24 @@
+pub mod account;
+pub mod app;
+pub mod certs;
+pub mod chat;
+pub mod config;
+pub mod contacts;
+pub mod crypto;
+pub mod db;
+pub mod error;
+pub mod geohash;
+pub mod health;
+pub mod keys;
+pub mod matching;
+pub mod metrics;
+pub mod moderation;
+pub mod predicate;
+pub mod profile;
+pub mod recovery;
+pub mod redact;
+pub mod regions;
+pub mod reveal;
+pub mod shares;
+pub mod telemetry;
+pub mod token;
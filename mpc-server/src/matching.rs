@@ -1,48 +1,53 @@
+use ark_ff::PrimeField;
 use co_noir::{
     AcirFormat, Address, Bn254, NetworkConfig, NetworkParty, PartyID, Poseidon2Sponge,
     Rep3CoUltraHonk, Rep3MpcNet, UltraHonk, merge_input_shares,
 };
 use co_ultrahonk::prelude::{ProverCrs, ZeroKnowledge};
 use noirc_artifacts::program::ProgramArtifact;
-use once_cell::sync::Lazy;
 use rayon::prelude::*;
-use rustls::pki_types::CertificateDer;
-use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
-use std::{
-    path::PathBuf,
-    sync::Arc,
-    thread,
-    time::{Duration, Instant},
-};
+use rustls::pki_types::PrivateKeyDer;
+use std::{sync::Arc, thread, time::Instant};
+use tracing::Span;
 
+use crate::certs::{self, PartyCerts};
+use crate::config::config;
 use crate::db::{
-    connect_db, get_all_users, get_user, insert_matches, update_checked, update_checked_many,
+    User, connect_db, get_all_users, get_blocked, get_user, insert_matches, update_checked,
+    update_checked_many,
 };
+use crate::error::{Error, Result};
+use crate::health::{self, Session};
+use crate::metrics::{self, EVALUATIONS, MATCHES_FOUND};
+use crate::profile::CIRCUIT_ABI_VERSION;
 use crate::shares::{Share, get_shares};
-
-pub const DATA_DIR: Lazy<PathBuf> =
-    Lazy::new(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data"));
-pub const CONFIG_DIR: Lazy<PathBuf> =
-    Lazy::new(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("config"));
-pub const SHARES_DIR_1: Lazy<PathBuf> = Lazy::new(|| DATA_DIR.join("user1"));
-pub const SHARES_DIR_2: Lazy<PathBuf> = Lazy::new(|| DATA_DIR.join("user2"));
+use crate::telemetry;
 
 pub async fn run_matches(
     user_id: String,
-    parties_certs: [CertificateDer<'static>; 3],
     program_artifact: &ProgramArtifact,
     constraint_system: Arc<AcirFormat<ark_bn254::Fr>>,
     recursive: bool,
     has_zk: ZeroKnowledge,
     prover_crs: Arc<ProverCrs<Bn254>>,
     verifier_crs: Arc<ark_bn254::G2Affine>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+) -> Result<()> {
     let conn = connect_db()?;
 
     let user1 = get_user(&conn, &user_id)?;
-    let all_users = get_all_users(&conn)?
+    if user1.suspended {
+        return Err(Error::Forbidden("Account suspended".to_string()));
+    }
+    if user1.abi_version != CIRCUIT_ABI_VERSION {
+        return Err(Error::Conflict(
+            "Profile uploaded for an older version of the circuit, upload it again".to_string(),
+        ));
+    }
+    // blocked pairs are never evaluated again, even after new shares
+    let blocked = get_blocked(&conn, &user_id)?;
+    let all_users = get_all_users(&conn, CIRCUIT_ABI_VERSION)?
         .into_iter()
-        .filter(|u| !user1.checked.contains(&u.id))
+        .filter(|u| !user1.checked.contains(&u.id) && !blocked.contains(&u.id))
         .collect::<Vec<_>>();
 
     update_checked(
@@ -60,51 +65,95 @@ pub async fn run_matches(
         .map(|u| u.id.clone())
         .collect::<Vec<String>>();
 
-    println!("ALL USERS: {:?}", all_users.len());
+    let session_span = tracing::info_span!(
+        "session",
+        session_id = %telemetry::session_id(),
+        circuit_version = %health::circuit_version(),
+    );
+    let _session_span = session_span.enter();
+    tracing::info!(pairs = all_users.len(), "starting match session");
+
+    // the whole batch uses the same certificates, even if they are rotated meanwhile
+    let accepted_certs = certs::accepted()?;
+    let session = Session::start(all_users.len());
 
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(5).build()?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config().mpc.threads)
+        .build()
+        .map_err(|e| Error::Internal(e.to_string()))?;
 
-    let verified_matches = pool.install(|| -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let verified_matches = pool.install(|| -> Result<Vec<(User, u32)>> {
         all_users
             .into_par_iter()
             .enumerate()
-            .map(|(thread_id, user2)| -> Result<String, Box<dyn std::error::Error + Send + Sync + 'static>> {
-                            let shares_user1 = get_shares(&user1.id, true)?;
-                            let shares_user2 = get_shares(&user2.id, false)?;
-
-                            let share0 = merge_shares(shares_user1[0].clone(), shares_user2[0].clone())?;
-                            let share1 = merge_shares(shares_user1[1].clone(), shares_user2[1].clone())?;
-                            let share2 = merge_shares(shares_user1[2].clone(), shares_user2[2].clone())?;
-
-                            match run_match(
-                                thread_id,
-                                [share0, share1, share2],
-                                parties_certs.clone(),
-                                program_artifact,
-                                constraint_system.clone(),
-                                recursive,
-                                has_zk,
-                                prover_crs.clone(),
-                                verifier_crs.clone(),
-                            ) {
-                                Ok(_) => Ok(user2.id),
-                                Err(e) => Err(e),
-                            }
-                        },
-                    )
-                    .filter(|m| m.is_ok())
-                    .collect::<Result<Vec<String>, _>>()
+            .map(|(thread_id, user2)| -> Result<(User, u32)> {
+                let _span = tracing::info_span!(
+                    parent: &session_span,
+                    "evaluation",
+                    pair_id = %telemetry::pair_id(&user1.id, &user2.id),
+                )
+                .entered();
+
+                let shares_user1 = get_shares(&user1.id, user1.share_version, true)?;
+                let shares_user2 = get_shares(&user2.id, user2.share_version, false)?;
+
+                let share0 = merge_shares(shares_user1[0].clone(), shares_user2[0].clone())?;
+                let share1 = merge_shares(shares_user1[1].clone(), shares_user2[1].clone())?;
+                let share2 = merge_shares(shares_user1[2].clone(), shares_user2[2].clone())?;
+
+                // during a rotation overlap, retry with the previous certificates
+                let mut result = Err(Error::Internal("No party certificates".to_string()));
+                for parties_certs in &accepted_certs {
+                    result = run_match(
+                        thread_id,
+                        [share0.clone(), share1.clone(), share2.clone()],
+                        parties_certs,
+                        program_artifact,
+                        constraint_system.clone(),
+                        recursive,
+                        has_zk,
+                        prover_crs.clone(),
+                        verifier_crs.clone(),
+                    );
+                    if !matches!(result, Err(Error::MpcNetwork(_))) {
+                        break;
+                    }
+                }
+                session.pair_done();
+                EVALUATIONS.inc();
+                if let Err(e) = &result {
+                    if matches!(e, Error::NoMatch) {
+                        tracing::info!("no match");
+                    } else {
+                        tracing::warn!(cause = e.code(), "evaluation failed");
+                    }
+                    metrics::record_failure(e);
+                }
+                result.map(|score| (user2, score))
+            })
+            .filter(|m| m.is_ok())
+            .collect::<Result<Vec<_>, _>>()
     })?;
 
-    println!("verified matches: {verified_matches:?}");
+    // the scores are kept, even below the thresholds, since they can be lowered later
+    let revealed = verified_matches
+        .iter()
+        .filter(|(user2, score)| *score >= user1.min_score && *score >= user2.min_score)
+        .count();
+    tracing::info!(
+        evaluated = verified_matches.len(),
+        matches = revealed,
+        "match session done"
+    );
+    MATCHES_FOUND.inc_by(revealed as u64);
 
     update_checked_many(&conn, users2, vec![user_id.clone()])?;
 
     insert_matches(
         &conn,
         verified_matches
-            .iter()
-            .map(|m| (user_id.clone(), m.clone()))
+            .into_iter()
+            .map(|(user2, score)| (user_id.clone(), user2.id, score))
             .collect(),
     )?;
     Ok(())
@@ -113,45 +162,41 @@ pub async fn run_matches(
 pub fn run_match(
     thread_id: usize,
     [share0, share1, share2]: [Share; 3],
-    parties_certs: [CertificateDer<'static>; 3],
+    parties_certs: &PartyCerts,
     program_artifact: &ProgramArtifact,
     constraint_system: Arc<AcirFormat<ark_bn254::Fr>>,
     recursive: bool,
     has_zk: ZeroKnowledge,
     prover_crs: Arc<ProverCrs<Bn254>>,
     verifier_crs: Arc<ark_bn254::G2Affine>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+) -> Result<u32> {
     let match_time = Instant::now();
 
-    let party0_port = 10000 + thread_id as u16;
-    let party1_port = 11000 + thread_id as u16;
-    let party2_port = 12000 + thread_id as u16;
+    let [party0_port, party1_port, party2_port] =
+        config().mpc.party_ports.map(|port| port + thread_id as u16);
 
     let parties = vec![
         NetworkParty::new(
             PartyID::ID0.into(),
             Address::new("localhost".to_string(), party0_port),
-            parties_certs[0].clone(),
+            parties_certs.certs[0].clone(),
         ),
         NetworkParty::new(
             PartyID::ID1.into(),
             Address::new("localhost".to_string(), party1_port),
-            parties_certs[1].clone(),
+            parties_certs.certs[1].clone(),
         ),
         NetworkParty::new(
             PartyID::ID2.into(),
             Address::new("localhost".to_string(), party2_port),
-            parties_certs[2].clone(),
+            parties_certs.certs[2].clone(),
         ),
     ];
 
     let data0 = DataForThread {
         id: PartyID::ID0,
         port: party0_port,
-        key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(std::fs::read(
-            CONFIG_DIR.join("key0.der"),
-        )?))
-        .clone_key(),
+        key: parties_certs.key(0),
         parties: parties.clone(),
         share: share0,
         program_artifact: program_artifact.clone(),
@@ -164,10 +209,7 @@ pub fn run_match(
     let data1 = DataForThread {
         id: PartyID::ID1,
         port: party1_port,
-        key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(std::fs::read(
-            CONFIG_DIR.join("key1.der"),
-        )?))
-        .clone_key(),
+        key: parties_certs.key(1),
         parties: parties.clone(),
         share: share1,
         program_artifact: program_artifact.clone(),
@@ -180,10 +222,7 @@ pub fn run_match(
     let data2 = DataForThread {
         id: PartyID::ID2,
         port: party2_port,
-        key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(std::fs::read(
-            CONFIG_DIR.join("key2.der"),
-        )?))
-        .clone_key(),
+        key: parties_certs.key(2),
         parties: parties.clone(),
         share: share2,
         program_artifact: program_artifact.clone(),
@@ -195,28 +234,40 @@ pub fn run_match(
     };
 
     let handles = vec![
-        thread::spawn(move || spawn_party(data0)),
-        thread::spawn(move || spawn_party(data1)),
-        thread::spawn(move || spawn_party(data2)),
+        thread::spawn(in_current_span(move || spawn_party(data0))),
+        thread::spawn(in_current_span(move || spawn_party(data1))),
+        thread::spawn(in_current_span(move || spawn_party(data2))),
     ];
 
+    let mut scores = Vec::new();
     for handle in handles {
-        let verified = handle.join().unwrap()?;
+        let (verified, score) = handle
+            .join()
+            .map_err(|_| Error::Internal("MPC party thread panicked".to_string()))??;
         if !verified {
-            return Err("Proof verification failed".into());
+            return Err(Error::Proof("Proof verification failed".to_string()));
         }
+        scores.push(score);
+    }
+    // the score is a public output, all the parties see the same one
+    if scores.iter().any(|s| *s != scores[0]) {
+        return Err(Error::Proof(
+            "The parties disagree on the score".to_string(),
+        ));
     }
 
-    println!("match time: {:?}", match_time.elapsed());
+    tracing::info!(
+        elapsed_ms = match_time.elapsed().as_millis() as u64,
+        "match verified"
+    );
 
-    Ok(())
+    Ok(scores[0])
 }
 
-fn merge_shares(
-    share_user1: Share,
-    share_user2: Share,
-) -> Result<Share, Box<dyn std::error::Error + Send + Sync>> {
-    let merged = merge_input_shares::<Bn254>(vec![share_user1, share_user2])?;
+/// Combines the shares of user1 and user2 into the input shares of a party
+pub fn merge_shares(share_user1: Share, share_user2: Share) -> Result<Share> {
+    let merged = merge_input_shares::<Bn254>(vec![share_user1, share_user2])
+        .map_err(|e| Error::Storage(format!("Invalid shares: {e}")))?;
     Ok(merged)
 }
 
@@ -234,9 +285,8 @@ struct DataForThread {
     verifier_crs: Arc<ark_bn254::G2Affine>,
 }
 
-fn spawn_party(
-    data: DataForThread,
-) -> Result<bool, Box<dyn std::error::Error + Send + Sync + 'static>> {
+/// Returns whether the proof is valid, and the score output by the circuit
+fn spawn_party(data: DataForThread) -> Result<(bool, u32)> {
     let DataForThread {
         id,
         port,
@@ -251,36 +301,82 @@ fn spawn_party(
         verifier_crs,
     } = data;
 
+    let party: usize = id.into();
+    let _span = tracing::info_span!("party", party).entered();
+
     let start_network = Instant::now();
     let network_config = NetworkConfig::new(
-        id.into(),
-        format!("[::]:{}", port).parse()?,
+        party,
+        format!("[::]:{}", port)
+            .parse()
+            .map_err(|e| Error::Internal(format!("Invalid bind address: {e}")))?,
         key,
         parties,
-        Some(Duration::from_secs(60)),
+        Some(config().mpc_timeout()),
     );
-    let net = Rep3MpcNet::new(network_config)?;
-    println!("network setup time: {:?}", start_network.elapsed());
+    let net = Rep3MpcNet::new(network_config).map_err(|e| Error::MpcNetwork(e.to_string()));
+    health::record_network_setup(port, net.as_ref().err());
+    let net = net?;
+    phase_done("network_setup", party, start_network);
 
     let total_time = Instant::now();
 
     let witness_time = Instant::now();
-    let (witness_share, net) = co_noir::generate_witness_rep3(share, program_artifact, net)?;
-    println!("witness time: {:?}", witness_time.elapsed());
+    // fails if the circuit assertions fail, i.e. if the users don't match
+    let (witness_share, net) = co_noir::generate_witness_rep3(share, program_artifact, net)
+        .map_err(|e| {
+            tracing::debug!("witness generation failed: {e}");
+            Error::NoMatch
+        })?;
+    phase_done("witness", party, witness_time);
 
     let pk_time = Instant::now();
     let (pk, net) =
-        co_noir::generate_proving_key_rep3(net, &constraint_system, witness_share, recursive)?;
-    let vk = pk.create_vk(&prover_crs, *verifier_crs)?;
-    println!("pk time: {:?}", pk_time.elapsed());
+        co_noir::generate_proving_key_rep3(net, &constraint_system, witness_share, recursive)
+            .map_err(|e| Error::Proof(format!("Proving key generation failed: {e}")))?;
+    let vk = pk
+        .create_vk(&prover_crs, *verifier_crs)
+        .map_err(|e| Error::Proof(format!("Verification key generation failed: {e}")))?;
+    let score = score(&pk.public_inputs)?;
+    phase_done("proving_key", party, pk_time);
 
     let proof_time = Instant::now();
-    let (proof, _) = Rep3CoUltraHonk::<_, _, Poseidon2Sponge>::prove(net, pk, &prover_crs, has_zk)?;
-    println!("proof time: {:?}", proof_time.elapsed());
+    let (proof, _) = Rep3CoUltraHonk::<_, _, Poseidon2Sponge>::prove(net, pk, &prover_crs, has_zk)
+        .map_err(|e| Error::Proof(format!("Proving failed: {e}")))?;
+    phase_done("proof", party, proof_time);
+    phase_done("total", party, total_time);
+
+    let verify_time = Instant::now();
+    let verified = UltraHonk::<_, Poseidon2Sponge>::verify(proof, &vk, has_zk)
+        .map_err(|e| Error::Proof(format!("Verification failed: {e}")))?;
+    phase_done("verify", party, verify_time);
+
+    Ok((verified, score))
+}
 
-    println!("TOTAL time: {:?}", total_time.elapsed());
+/// The only public input is the score returned by `main`, a `u8`
+fn score(public_inputs: &[ark_bn254::Fr]) -> Result<u32> {
+    let [score] = public_inputs else {
+        return Err(Error::Proof(format!(
+            "Expected 1 public input, got {}",
+            public_inputs.len()
+        )));
+    };
+    let limbs = score.into_bigint().0;
+    match u8::try_from(limbs[0]) {
+        Ok(score) if limbs[1..].iter().all(|limb| *limb == 0) => Ok(score as u32),
+        _ => Err(Error::Proof("Invalid score".to_string())),
+    }
+}
 
-    let verified = UltraHonk::<_, Poseidon2Sponge>::verify(proof, &vk, has_zk)?;
+/// Runs `f` in the span of the calling thread
+fn in_current_span<T>(f: impl FnOnce() -> T) -> impl FnOnce() -> T {
+    let span = Span::current();
+    move || span.in_scope(f)
+}
 
-    Ok(verified)
+fn phase_done(phase: &'static str, party: usize, start: Instant) {
+    let elapsed = start.elapsed();
+    tracing::debug!(phase, elapsed_ms = elapsed.as_millis() as u64, "phase done");
+    metrics::observe_phase(phase, party, elapsed.as_secs_f64());
 }
@@ -0,0 +1,91 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder, exponential_buckets,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+};
+
+use crate::error::{Error, Result};
+
+// Prometheus metrics, exposed on `/metrics`.
+
+/// Duration of each MPC phase: `network_setup`, `witness`, `proving_key`, `proof`, `total`
+/// (without the network setup) and `verify`
+pub static PHASE_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "comatch_mpc_phase_seconds",
+        "Duration of the MPC and proving phases",
+        &["phase", "party"],
+        // 10ms to ~80s
+        exponential_buckets(0.01, 2.0, 14).expect("valid buckets")
+    )
+    .expect("metric registered once")
+});
+
+pub static EVALUATIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("comatch_evaluations_total", "Pairs evaluated")
+        .expect("metric registered once")
+});
+
+pub static MATCHES_FOUND: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("comatch_matches_found_total", "Verified matches")
+        .expect("metric registered once")
+});
+
+/// Failed evaluations, by error code. Pairs that don't match fail at witness generation and are
+/// counted as `no_match`, `proof_failed` is left for the prover errors.
+pub static FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "comatch_evaluation_failures_total",
+        "Failed evaluations by cause",
+        &["cause"]
+    )
+    .expect("metric registered once")
+});
+
+pub static UPLOADS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("comatch_uploads_total", "Shares uploaded")
+        .expect("metric registered once")
+});
+
+pub static ACTIVE_SESSIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("comatch_active_sessions", "Match sessions running")
+        .expect("metric registered once")
+});
+
+pub static PENDING_PAIRS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "comatch_pending_pairs",
+        "Pairs waiting to be evaluated, across all sessions"
+    )
+    .expect("metric registered once")
+});
+
+/// Registers all the metrics, so they are exported before their first update
+pub fn init() {
+    Lazy::force(&PHASE_SECONDS);
+    Lazy::force(&EVALUATIONS);
+    Lazy::force(&MATCHES_FOUND);
+    Lazy::force(&FAILURES);
+    Lazy::force(&UPLOADS);
+    Lazy::force(&ACTIVE_SESSIONS);
+    Lazy::force(&PENDING_PAIRS);
+}
+
+pub fn observe_phase(phase: &str, party: usize, seconds: f64) {
+    PHASE_SECONDS
+        .with_label_values(&[phase, &party.to_string()])
+        .observe(seconds);
+}
+
+pub fn record_failure(error: &Error) {
+    FAILURES.with_label_values(&[error.code()]).inc();
+}
+
+/// All the metrics, in the Prometheus text format
+pub fn render() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|e| Error::Internal(e.to_string()))?;
+    String::from_utf8(buffer).map_err(|e| Error::Internal(e.to_string()))
+}
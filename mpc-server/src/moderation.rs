@@ -0,0 +1,245 @@
+use axum::{RequestPartsExt, extract::FromRequestParts, http::request::Parts};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::{
+        self, ReportRow, close_report, connect_db, count_reports, insert_block, insert_report,
+        set_suspended,
+    },
+    error::{AuthError, Error, Result},
+    redact::Redacted,
+    token::now,
+};
+
+// Blocking and reporting.
+//
+// A user can block any match they see: it disappears for both users, like a decline, and the pair
+// is never evaluated again, even after new shares. Reporting a match also blocks it, and puts it
+// in the moderation queue, reviewed with the admin API.
+//
+// Reports keep the preferences private: they only hold the two users, the reason and what the
+// reporter wrote. The score of the match and the shares are never shown to the moderators.
+//
+// Reports outlive the accounts of both users. Ids are random and the server knows nothing durable
+// about a user, so a suspended user can still delete their account and upload a new profile: the
+// suspension only holds for the account, and the new one starts without reports.
+
+const MAX_DETAILS_LEN: usize = 1000;
+
+/// Hash of the admin API token, from `ADMIN_TOKEN` or the file at `ADMIN_TOKEN_FILE`.
+/// The admin API is disabled when neither is set.
+static ADMIN_TOKEN_HASH: Lazy<Option<[u8; 32]>> = Lazy::new(|| {
+    let token = match (
+        std::env::var("ADMIN_TOKEN"),
+        std::env::var("ADMIN_TOKEN_FILE"),
+    ) {
+        (Ok(token), _) => token,
+        (Err(_), Ok(path)) => std::fs::read_to_string(path).ok()?,
+        _ => return None,
+    };
+    let token = token.trim();
+    (!token.is_empty()).then(|| Sha256::digest(token.as_bytes()).into())
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    Spam,
+    Harassment,
+    FakeProfile,
+    Underage,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    #[default]
+    Open,
+    Dismissed,
+    /// The reported user was suspended
+    Actioned,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewAction {
+    Dismiss,
+    /// Suspends the reported user: they aren't matched anymore, and their matches are hidden
+    Suspend,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportRequest {
+    pub reason: Reason,
+    pub details: Option<Redacted<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportQuery {
+    #[serde(default)]
+    pub status: ReportStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewRequest {
+    pub action: ReviewAction,
+}
+
+/// A report, as seen by the moderators
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub id: i64,
+    pub match_id: i64,
+    pub reporter_id: String,
+    pub reported_id: String,
+    pub reason: String,
+    pub details: Option<String>,
+    pub status: String,
+    pub created_at: u64,
+    pub reviewed_at: Option<u64>,
+    /// Reports filed against the reported user, this one included
+    pub reports_against: u32,
+    /// The reported user deleted their account
+    pub reported_deleted: bool,
+}
+
+/// Requests authenticated with the admin API token
+#[derive(Debug)]
+pub struct Admin;
+
+impl Reason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Reason::Spam => "spam",
+            Reason::Harassment => "harassment",
+            Reason::FakeProfile => "fake_profile",
+            Reason::Underage => "underage",
+            Reason::Other => "other",
+        }
+    }
+}
+
+impl ReportStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Dismissed => "dismissed",
+            ReportStatus::Actioned => "actioned",
+        }
+    }
+}
+
+/// Blocks the other user of a match
+pub fn block(user_id: &str, match_id: i64) -> Result<()> {
+    let conn = connect_db()?;
+    let row = db::get_match(&conn, user_id, match_id)?;
+    insert_block(&conn, user_id, &row.other_id)
+}
+
+/// Blocks the other user of a match and files a report against them
+pub fn report(user_id: &str, match_id: i64, request: ReportRequest) -> Result<()> {
+    let details = request
+        .details
+        .as_ref()
+        .map(|d| d.expose().trim())
+        .filter(|d| !d.is_empty());
+    if details.is_some_and(|d| d.chars().count() > MAX_DETAILS_LEN) {
+        return Err(Error::Validation(format!(
+            "Details are limited to {MAX_DETAILS_LEN} characters"
+        )));
+    }
+
+    let mut conn = connect_db()?;
+    let tx = conn.transaction()?;
+    let row = db::get_match(&tx, user_id, match_id)?;
+    insert_report(
+        &tx,
+        match_id,
+        user_id,
+        &row.other_id,
+        request.reason.as_str(),
+        details,
+    )?;
+    insert_block(&tx, user_id, &row.other_id)?;
+    tx.commit()?;
+
+    tracing::info!(reason = request.reason.as_str(), "match reported");
+    Ok(())
+}
+
+/// The moderation queue, oldest first
+pub fn get_reports(status: ReportStatus) -> Result<Vec<Report>> {
+    let conn = connect_db()?;
+    db::get_reports(&conn, status.as_str())?
+        .into_iter()
+        .map(|row| to_report(&conn, row))
+        .collect()
+}
+
+/// Closes an open report
+pub fn review(report_id: i64, action: ReviewAction) -> Result<Report> {
+    let mut conn = connect_db()?;
+    let tx = conn.transaction()?;
+    let row = db::get_report(&tx, report_id)?;
+    let status = match action {
+        ReviewAction::Dismiss => ReportStatus::Dismissed,
+        ReviewAction::Suspend => {
+            set_suspended(&tx, &row.reported_id)?;
+            ReportStatus::Actioned
+        }
+    };
+    if !close_report(&tx, report_id, status.as_str(), now())? {
+        return Err(Error::Conflict(
+            "The report was already reviewed".to_string(),
+        ));
+    }
+    tx.commit()?;
+
+    tracing::info!(report_id, status = status.as_str(), "report reviewed");
+    to_report(&conn, db::get_report(&conn, report_id)?)
+}
+
+fn to_report(conn: &rusqlite::Connection, row: ReportRow) -> Result<Report> {
+    Ok(Report {
+        reports_against: count_reports(conn, &row.reported_id)?,
+        id: row.id,
+        match_id: row.match_id,
+        reporter_id: row.reporter_id,
+        reported_id: row.reported_id,
+        reason: row.reason,
+        details: row.details,
+        status: row.status,
+        created_at: row.created_at,
+        reviewed_at: row.reviewed_at,
+        reported_deleted: row.reported_deleted,
+    })
+}
+
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let TypedHeader(Authorization(bearer)) = parts
+            .extract::<TypedHeader<Authorization<Bearer>>>()
+            .await
+            .map_err(|_| AuthError::MissingToken)?;
+        let Some(expected) = *ADMIN_TOKEN_HASH else {
+            return Err(Error::Forbidden("The admin API is disabled".to_string()));
+        };
+        // compare hashes, so the time taken doesn't depend on the token
+        if <[u8; 32]>::from(Sha256::digest(bearer.token().as_bytes())) != expected {
+            return Err(AuthError::InvalidToken.into());
+        }
+        Ok(Admin)
+    }
+}
@@ -0,0 +1,93 @@
+use crate::profile::MAX_INTEREST;
+use crate::shares::User;
+
+// The match rules of the circuit (`circuit/src`), in plaintext.
+//
+// This is only a reference to test the MPC pipeline against, the server never sees the
+// preferences in clear. Keep it in sync with the circuit.
+
+/// The score output by the circuit, the number of interests in common, or `None` if the circuit
+/// rejects the pair. A failing assertion in the circuit means no proof, so no match.
+pub fn score(user1: &User, user2: &User) -> Option<u32> {
+    let accepted = is_valid(user1)
+        && is_valid(user2)
+        && user1.id != user2.id
+        && check_age(user1, user2)
+        && check_gender(user1, user2)
+        && check_location(user1, user2)
+        && user1.interests.iter().all(|&i| i <= MAX_INTEREST);
+    accepted.then(|| count_common_interests(user1, user2))
+}
+
+/// Inputs that can't be encoded as the circuit's `User`
+fn is_valid(user: &User) -> bool {
+    let fits_u8 = |v: u32| v <= u8::MAX as u32;
+    fits_u8(user.age)
+        && fits_u8(user.gender)
+        && fits_u8(user.region)
+        && fits_u8(user.preferences.distance)
+        && user.interests.len() == 3
+        && user.interests.iter().all(|&i| fits_u8(i))
+        && fits_u8(user.preferences.age_min)
+        && fits_u8(user.preferences.age_max)
+        && fits_u8(user.preferences.gender)
+}
+
+fn check_age(user1: &User, user2: &User) -> bool {
+    let in_range = |user: &User, other: &User| {
+        user.age >= other.preferences.age_min && user.age <= other.preferences.age_max
+    };
+    in_range(user1, user2) && in_range(user2, user1)
+}
+
+/// Gender 0 or 1, preference 2 means any
+fn check_gender(user1: &User, user2: &User) -> bool {
+    let valid = user1.gender <= 1
+        && user2.gender <= 1
+        && user1.preferences.gender <= 2
+        && user2.preferences.gender <= 2;
+    let accepts = |user: &User, other: &User| {
+        user.preferences.gender == 2 || user.preferences.gender == other.gender
+    };
+    valid && accepts(user1, user2) && accepts(user2, user1)
+}
+
+/// Both distance bands must accept the pair
+fn check_location(user1: &User, user2: &User) -> bool {
+    let valid = user1.preferences.distance <= 3
+        && user2.preferences.distance <= 3
+        && user1.location < 1 << 26
+        && user2.location < 1 << 26;
+    valid
+        && accepts(user1.preferences.distance, user1, user2)
+        && accepts(user2.preferences.distance, user1, user2)
+}
+
+fn accepts(distance: u32, user1: &User, user2: &User) -> bool {
+    // cells are 0 when the location is unknown
+    let same_cell = |shift: u32| {
+        user1.location != 0
+            && user2.location != 0
+            && user1.location >> shift == user2.location >> shift
+    };
+    match distance {
+        0 => user1.region == user2.region,
+        1 => same_cell(5),
+        2 => same_cell(10),
+        _ => true,
+    }
+}
+
+/// Distinct interests of user1 that user2 also has.
+/// The circuit only checks the range of user1's interests.
+fn count_common_interests(user1: &User, user2: &User) -> u32 {
+    let mut common: Vec<u32> = user1
+        .interests
+        .iter()
+        .copied()
+        .filter(|i| user2.interests.contains(i))
+        .collect();
+    common.sort();
+    common.dedup();
+    common.len() as u32
+}
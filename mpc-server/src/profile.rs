@@ -0,0 +1,310 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::db::{self, connect_db, get_user};
+use crate::error::{Error, Result};
+use crate::geohash;
+use crate::regions::registry;
+use crate::shares::{Preferences, User};
+
+// Profile schema.
+//
+// The web app sends a versioned profile to `/split`, which is validated, migrated to the latest
+// version and mapped onto the inputs of the circuit (`shares::User`, `circuit/src/structs.nr`).
+// The shares only depend on the circuit inputs: when those change, `CIRCUIT_ABI_VERSION` is
+// bumped and users who uploaded shares for an older ABI have to upload them again.
+
+/// Latest version of the profile schema
+pub const CURRENT_VERSION: u32 = 2;
+/// Version of the circuit inputs. Stored with each user at upload.
+/// 2: location cell and distance band
+/// 3: same inputs as 2, but some shares tagged 2 were split while `circuit.json` was still built
+/// from the circuit of version 1
+pub const CIRCUIT_ABI_VERSION: u32 = 3;
+
+/// Interests are numbered from 0 to `MAX_INTEREST`
+pub const MAX_INTEREST: u32 = 8;
+/// Number of interests the circuit compares
+pub const CIRCUIT_INTERESTS: usize = 3;
+const MAX_WEIGHT: u32 = 10;
+
+/// Payloads without a version were sent by clients from before the schema was versioned
+pub fn legacy_version() -> u32 {
+    1
+}
+
+/// The original profile, mapped 1:1 to the circuit inputs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileV1 {
+    pub id: String,
+    pub age: u32,
+    pub gender: u32,
+    pub region: u32,
+    pub interests: Vec<u32>,
+    pub preferences: PreferencesV1,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreferencesV1 {
+    pub age_min: u32,
+    pub age_max: u32,
+    /// 2 means any
+    pub gender: u32,
+}
+
+/// Unknown fields are rejected: a setting the circuit can't apply, like dealbreakers, must not be
+/// silently dropped
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub id: String,
+    pub age: u32,
+    /// 0: zk researcher, 1: security researcher
+    pub gender: u32,
+    /// Id in the region registry, see `regions.rs`
+    pub region: u32,
+    /// Only given to the circuit as a geohash cell
+    #[serde(default)]
+    pub location: Option<Location>,
+    pub interests: Vec<Interest>,
+    pub preferences: ProfilePreferences,
+}
+
+/// Coordinates aren't accepted: they would reach the server, which only needs the cell.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Location {
+    /// A city of the registry
+    City { city: String },
+    /// Quantized by the client, so the coordinates never leave it
+    Geohash { geohash: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Interest {
+    pub id: u32,
+    /// From 1 to 10. Only ranks the interests: the 3 heaviest are compared, and the circuit counts
+    /// each common interest once, whatever its weight.
+    pub weight: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProfilePreferences {
+    pub age_min: u32,
+    pub age_max: u32,
+    /// Accepted genders
+    pub genders: Vec<u32>,
+    #[serde(default)]
+    pub distance: DistanceBand,
+}
+
+/// How far the other user can be. Both users' bands must accept the pair.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceBand {
+    #[default]
+    SameRegion,
+    /// Same ~40km cell
+    Nearby,
+    /// Same ~150km cell
+    Wide,
+    Anywhere,
+}
+
+impl From<ProfileV1> for Profile {
+    fn from(v1: ProfileV1) -> Self {
+        let genders = match v1.preferences.gender {
+            2 => vec![0, 1],
+            gender => vec![gender],
+        };
+        let mut interests: Vec<Interest> = Vec::new();
+        for id in v1.interests {
+            if !interests.iter().any(|i| i.id == id) {
+                interests.push(Interest { id, weight: 1 });
+            }
+        }
+        Profile {
+            id: v1.id,
+            age: v1.age,
+            gender: v1.gender,
+            region: v1.region,
+            location: None,
+            interests,
+            preferences: ProfilePreferences {
+                age_min: v1.preferences.age_min,
+                age_max: v1.preferences.age_max,
+                genders,
+                distance: DistanceBand::SameRegion,
+            },
+        }
+    }
+}
+
+/// Reads a profile of any version, migrated to the latest one and validated
+pub fn parse(version: u32, profile: Value) -> Result<Profile> {
+    let invalid = |e: serde_json::Error| Error::Validation(format!("Invalid profile: {e}"));
+    let profile = match version {
+        1 => serde_json::from_value::<ProfileV1>(profile)
+            .map_err(invalid)?
+            .into(),
+        2 => serde_json::from_value::<Profile>(profile).map_err(invalid)?,
+        version => {
+            return Err(Error::Validation(format!(
+                "Unsupported profile version {version}, the latest is {CURRENT_VERSION}"
+            )));
+        }
+    };
+    profile.validate()?;
+    Ok(profile)
+}
+
+impl Profile {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: &str| Err(Error::Validation(msg.to_string()));
+        let fits_u8 = |v: u32| v <= u8::MAX as u32;
+        let prefs = &self.preferences;
+
+        if !fits_u8(self.age) || !fits_u8(prefs.age_min) || !fits_u8(prefs.age_max) {
+            return invalid("Invalid age");
+        }
+        if prefs.age_min > prefs.age_max {
+            return invalid("age_min is greater than age_max");
+        }
+        if self.gender > 1 {
+            return invalid("Invalid gender");
+        }
+        let genders: HashSet<_> = prefs.genders.iter().collect();
+        if genders.is_empty()
+            || genders.len() != prefs.genders.len()
+            || prefs.genders.iter().any(|&g| g > 1)
+        {
+            return invalid("Invalid gender preferences");
+        }
+        if registry()?.region(self.region).is_none() {
+            return invalid("Unknown region");
+        }
+        self.cell()?;
+        if self.interests.is_empty() {
+            return invalid("At least one interest is required");
+        }
+        let mut ids = HashSet::new();
+        for interest in &self.interests {
+            if interest.id > MAX_INTEREST || !ids.insert(interest.id) {
+                return invalid("Invalid interests");
+            }
+            if interest.weight == 0 || interest.weight > MAX_WEIGHT {
+                return invalid("Invalid interest weight");
+            }
+        }
+        Ok(())
+    }
+
+    /// Inputs of the circuit, for the current ABI.
+    /// Fails for settings the circuit can't check yet.
+    pub fn to_circuit_input(&self) -> Result<User> {
+        let prefs = &self.preferences;
+        let cell = self.cell()?;
+        let distance = match prefs.distance {
+            DistanceBand::SameRegion => 0,
+            DistanceBand::Nearby => 1,
+            DistanceBand::Wide => 2,
+            DistanceBand::Anywhere => 3,
+        };
+        if cell.is_none() && matches!(prefs.distance, DistanceBand::Nearby | DistanceBand::Wide) {
+            return Err(Error::Validation(
+                "A location is required for this distance".to_string(),
+            ));
+        }
+
+        let gender = match prefs.genders.as_slice() {
+            [gender] => *gender,
+            // both
+            _ => 2,
+        };
+
+        // the most important interests, padded with duplicates which don't change the overlap
+        let mut interests = self.interests.clone();
+        interests.sort_by_key(|i| std::cmp::Reverse(i.weight));
+        let mut interests: Vec<u32> = interests
+            .iter()
+            .take(CIRCUIT_INTERESTS)
+            .map(|i| i.id)
+            .collect();
+        while interests.len() < CIRCUIT_INTERESTS {
+            interests.push(interests[0]);
+        }
+
+        Ok(User {
+            age: self.age,
+            gender: self.gender,
+            id: self.id.clone(),
+            interests,
+            region: self.region,
+            location: cell.map(geohash::circuit_cell).unwrap_or(0),
+            preferences: Preferences {
+                age_max: prefs.age_max,
+                age_min: prefs.age_min,
+                gender,
+                distance,
+            },
+        })
+    }
+
+    /// Geohash cell of the location
+    fn cell(&self) -> Result<Option<u32>> {
+        let cell = match &self.location {
+            None => return Ok(None),
+            Some(Location::City { city }) => {
+                let (region, city) = registry()?
+                    .city(city)
+                    .ok_or(Error::Validation("Unknown city".to_string()))?;
+                if region.id != self.region {
+                    return Err(Error::Validation(format!(
+                        "{} is not in this region",
+                        city.name
+                    )));
+                }
+                geohash::encode(city.lat, city.lon)?
+            }
+            Some(Location::Geohash { geohash }) => geohash::parse(geohash)?,
+        };
+        Ok(Some(cell))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileStatus {
+    /// Latest profile schema, the web app should send this one
+    pub schema_version: u32,
+    pub abi_version: u32,
+    /// The shares were split for an older circuit and can't be matched anymore
+    pub needs_reupload: bool,
+    pub min_score: u32,
+    /// Shares used for matching, see `PUT /profile/shares`
+    pub share_version: u32,
+}
+
+pub fn status(user_id: &str) -> Result<ProfileStatus> {
+    let user = get_user(&connect_db()?, user_id)?;
+    Ok(ProfileStatus {
+        schema_version: CURRENT_VERSION,
+        abi_version: user.abi_version,
+        needs_reupload: user.abi_version != CIRCUIT_ABI_VERSION,
+        min_score: user.min_score,
+        share_version: user.share_version,
+    })
+}
+
+/// Matches are only revealed when their score (the number of interests in common) reaches the
+/// `min_score` of both users. 0 accepts users without any interest in common.
+pub fn set_min_score(user_id: &str, min_score: u32) -> Result<()> {
+    if min_score > CIRCUIT_INTERESTS as u32 {
+        return Err(Error::Validation(format!(
+            "min_score must be at most {CIRCUIT_INTERESTS}"
+        )));
+    }
+    db::set_min_score(&connect_db()?, user_id, min_score)
+}
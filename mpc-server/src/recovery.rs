@@ -0,0 +1,73 @@
+use rand::{Rng, distributions::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::{
+        check_recovery_code, connect_db, delete_recovery_code, revoke_refresh_tokens,
+        set_recovery_code,
+    },
+    error::{AuthError, Result},
+    redact::Redacted,
+    token::{TokenPair, issue_tokens},
+};
+
+// Opt-in account recovery.
+//
+// The recovery code is `<user_id>-<secret>`. Only the hash of the whole code is stored,
+// so it can't be recovered from the database, and the user has to keep it somewhere safe.
+
+const SECRET_LEN: usize = 32;
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCode {
+    pub recovery_code: Redacted<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecoverRequest {
+    pub recovery_code: Redacted<String>,
+}
+
+/// Creates a recovery code for the user, replacing the previous one if any.
+pub fn create_recovery_code(user_id: &str) -> Result<RecoveryCode> {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SECRET_LEN)
+        .map(char::from)
+        .collect();
+    let recovery_code = format!("{user_id}-{secret}");
+
+    let conn = connect_db()?;
+    set_recovery_code(&conn, user_id, &hash_recovery_code(&recovery_code))?;
+
+    Ok(RecoveryCode {
+        recovery_code: recovery_code.into(),
+    })
+}
+
+pub fn disable_recovery(user_id: &str) -> Result<()> {
+    let conn = connect_db()?;
+    delete_recovery_code(&conn, user_id)?;
+    Ok(())
+}
+
+/// Issues new tokens for the owner of the recovery code.
+/// Existing refresh tokens are revoked, since the old device is probably lost.
+pub fn recover(recovery_code: &str) -> Result<TokenPair> {
+    let (user_id, _) = recovery_code
+        .split_once('-')
+        .ok_or(AuthError::InvalidRecoveryCode)?;
+
+    let conn = connect_db()?;
+    if !check_recovery_code(&conn, user_id, &hash_recovery_code(recovery_code))? {
+        return Err(AuthError::InvalidRecoveryCode.into());
+    }
+    revoke_refresh_tokens(&conn, user_id)?;
+
+    issue_tokens(user_id.to_string())
+}
+
+fn hash_recovery_code(recovery_code: &str) -> String {
+    hex::encode(Sha256::digest(recovery_code.as_bytes()))
+}
@@ -0,0 +1,75 @@
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::config::config;
+use crate::error::{Error, Result};
+
+// Registry of the regions users can pick, and of the cities they can give as their location.
+// The circuit only compares region ids, the server checks that they exist.
+
+static REGISTRY: OnceCell<Registry> = OnceCell::new();
+
+/// Used when there is no `regions.toml` in `config_dir`
+const DEFAULT_REGISTRY: &str = include_str!("../regions.toml");
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Registry {
+    pub regions: Vec<Region>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Region {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub cities: Vec<City>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct City {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+pub fn registry() -> Result<&'static Registry> {
+    REGISTRY.get_or_try_init(|| {
+        let registry = match std::fs::read_to_string(config().config_dir.join("regions.toml")) {
+            Ok(registry) => registry,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DEFAULT_REGISTRY.to_string(),
+            Err(e) => return Err(e.into()),
+        };
+        let registry: Registry = toml::from_str(&registry)
+            .map_err(|e| Error::Internal(format!("Invalid regions.toml: {e}")))?;
+
+        let mut ids = HashSet::new();
+        for region in &registry.regions {
+            // region ids are a u8 in the circuit
+            if region.id > u8::MAX as u32 || !ids.insert(region.id) {
+                return Err(Error::Internal(format!(
+                    "Invalid region id {} in regions.toml",
+                    region.id
+                )));
+            }
+        }
+        Ok(registry)
+    })
+}
+
+impl Registry {
+    pub fn region(&self, id: u32) -> Option<&Region> {
+        self.regions.iter().find(|r| r.id == id)
+    }
+
+    /// Case insensitive
+    pub fn city(&self, name: &str) -> Option<(&Region, &City)> {
+        self.regions.iter().find_map(|region| {
+            region
+                .cities
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name))
+                .map(|city| (region, city))
+        })
+    }
+}
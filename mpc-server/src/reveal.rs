@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use crate::{
+    config::config,
+    contacts::{Channel, SharedContact, shared_contacts},
+    db::{self, MatchRow, connect_db, expire_matches, update_match_state},
+    error::{Error, Result},
+    token::now,
+};
+
+// Mutual opt-in reveal of the contacts, and the lifecycle of a match.
+//
+// A match starts `pending`. Each user accepts or declines it, and the contacts are only disclosed
+// once both accepted:
+//
+//   pending -> accepted_by_user1 / accepted_by_user2 -> revealed -> unmatched
+//           \-> declined (by either user, before the reveal)
+//           \-> expired (not accepted by both within `match_ttl_secs`)
+//
+// A user never learns that the other one accepted first, nor that they declined: a declined
+// match just disappears. Once revealed, either user can unmatch: the contacts are hidden again
+// and the chat closes. Unmatched and expired matches are kept, so the pair isn't matched again.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevealState {
+    Pending,
+    AcceptedByUser1,
+    AcceptedByUser2,
+    Revealed,
+    Declined,
+    Unmatched,
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Accept,
+    Decline,
+    Unmatch,
+}
+
+/// The state of a match, as seen by one of the two users
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchState {
+    Pending,
+    /// Waiting for the other user
+    Accepted,
+    Revealed,
+    Unmatched,
+    Expired,
+}
+
+/// Which matches `GET /matches` returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchFilter {
+    /// Pending, accepted and revealed
+    #[default]
+    Active,
+    Pending,
+    Accepted,
+    Revealed,
+    Unmatched,
+    Expired,
+    All,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MatchQuery {
+    #[serde(default)]
+    pub state: MatchFilter,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Match {
+    pub id: i64,
+    pub state: MatchState,
+    pub score: u32,
+    /// When the match expires if both users haven't accepted it by then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Contacts the other user chose to share, only once both users accepted
+    pub contacts: Vec<SharedContact>,
+    /// Their twitter contact, for clients from before contacts had channels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub twitter_handle: Option<String>,
+}
+
+impl RevealState {
+    fn as_str(self) -> &'static str {
+        match self {
+            RevealState::Pending => "pending",
+            RevealState::AcceptedByUser1 => "accepted_by_user1",
+            RevealState::AcceptedByUser2 => "accepted_by_user2",
+            RevealState::Revealed => "revealed",
+            RevealState::Declined => "declined",
+            RevealState::Unmatched => "unmatched",
+            RevealState::Expired => "expired",
+        }
+    }
+
+    fn parse(state: &str) -> Result<Self> {
+        Ok(match state {
+            "pending" => RevealState::Pending,
+            "accepted_by_user1" => RevealState::AcceptedByUser1,
+            "accepted_by_user2" => RevealState::AcceptedByUser2,
+            "revealed" => RevealState::Revealed,
+            "declined" => RevealState::Declined,
+            "unmatched" => RevealState::Unmatched,
+            "expired" => RevealState::Expired,
+            state => return Err(Error::Internal(format!("Invalid match state {state}"))),
+        })
+    }
+
+    /// The state after `action` by user1 or user2 of the match
+    /// Not accepted by both users yet, so it can still expire
+    fn is_open(self) -> bool {
+        matches!(
+            self,
+            RevealState::Pending | RevealState::AcceptedByUser1 | RevealState::AcceptedByUser2
+        )
+    }
+
+    pub fn next(self, is_user1: bool, action: Action) -> Result<Self> {
+        use RevealState::*;
+
+        let accepted_by_me = if is_user1 {
+            AcceptedByUser1
+        } else {
+            AcceptedByUser2
+        };
+        match (self, action) {
+            (Declined, _) => Err(Error::NotFound("Match")),
+            (Unmatched, Action::Unmatch) => Ok(Unmatched),
+            (Unmatched | Expired, _) => Err(Error::Conflict("The match is over".to_string())),
+            (Revealed, Action::Accept) => Ok(Revealed),
+            (Revealed, Action::Decline) => Err(Error::Conflict(
+                "The match was already revealed, unmatch instead".to_string(),
+            )),
+            (Revealed, Action::Unmatch) => Ok(Unmatched),
+            // unmatching would tell the other user that they were turned down
+            (_, Action::Unmatch) => Err(Error::Conflict(
+                "The match isn't revealed yet, decline it instead".to_string(),
+            )),
+            (_, Action::Decline) => Ok(Declined),
+            (Pending, Action::Accept) => Ok(accepted_by_me),
+            (state, Action::Accept) if state == accepted_by_me => Ok(state),
+            // the other user accepted first
+            (_, Action::Accept) => Ok(Revealed),
+        }
+    }
+
+    fn view(self, is_user1: bool) -> Option<MatchState> {
+        match self {
+            RevealState::Pending => Some(MatchState::Pending),
+            RevealState::AcceptedByUser1 if is_user1 => Some(MatchState::Accepted),
+            RevealState::AcceptedByUser2 if !is_user1 => Some(MatchState::Accepted),
+            RevealState::AcceptedByUser1 | RevealState::AcceptedByUser2 => {
+                Some(MatchState::Pending)
+            }
+            RevealState::Revealed => Some(MatchState::Revealed),
+            RevealState::Unmatched => Some(MatchState::Unmatched),
+            RevealState::Expired => Some(MatchState::Expired),
+            RevealState::Declined => None,
+        }
+    }
+}
+
+impl MatchFilter {
+    fn includes(self, state: MatchState) -> bool {
+        match self {
+            MatchFilter::Active => matches!(
+                state,
+                MatchState::Pending | MatchState::Accepted | MatchState::Revealed
+            ),
+            MatchFilter::Pending => state == MatchState::Pending,
+            MatchFilter::Accepted => state == MatchState::Accepted,
+            MatchFilter::Revealed => state == MatchState::Revealed,
+            MatchFilter::Unmatched => state == MatchState::Unmatched,
+            MatchFilter::Expired => state == MatchState::Expired,
+            MatchFilter::All => true,
+        }
+    }
+}
+
+/// Matches of the user in the states of `filter`, best first. Declined matches are left out.
+pub fn get_matches(user_id: &str, filter: MatchFilter) -> Result<Vec<Match>> {
+    let conn = connect_db()?;
+    let matches = db::get_matches(&conn, user_id)?
+        .into_iter()
+        .filter_map(|m| to_match(&conn, m).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    Ok(matches
+        .into_iter()
+        .filter(|m| filter.includes(m.state))
+        .collect())
+}
+
+/// Accepts, declines or unmatches a match. Returns the match, or `None` once declined.
+pub fn respond(user_id: &str, match_id: i64, action: Action) -> Result<Option<Match>> {
+    let conn = connect_db()?;
+    // the other user can answer at the same time, retry if the state changed meanwhile
+    for _ in 0..3 {
+        let row = db::get_match(&conn, user_id, match_id)?;
+        let mut state = RevealState::parse(&row.state)?;
+        // the sweeper may not have run since the match expired
+        if state.is_open()
+            && config()
+                .match_expiry(row.created_at)
+                .is_some_and(|at| at < now())
+        {
+            let expired = RevealState::Expired;
+            if !update_match_state(&conn, match_id, state.as_str(), expired.as_str())? {
+                continue;
+            }
+            state = expired;
+        }
+        let next = state.next(row.is_user1, action)?;
+        if next == state || update_match_state(&conn, match_id, state.as_str(), next.as_str())? {
+            return to_match(
+                &conn,
+                MatchRow {
+                    state: next.as_str().to_string(),
+                    ..row
+                },
+            );
+        }
+    }
+    Err(Error::Conflict("The match changed, try again".to_string()))
+}
+
+/// Both users accepted the match
+pub fn is_revealed(row: &MatchRow) -> Result<bool> {
+    Ok(RevealState::parse(&row.state)? == RevealState::Revealed)
+}
+
+fn to_match(conn: &Connection, row: MatchRow) -> Result<Option<Match>> {
+    let Some(state) = RevealState::parse(&row.state)?.view(row.is_user1) else {
+        return Ok(None);
+    };
+    let contacts = match state {
+        MatchState::Revealed => shared_contacts(conn, &row.other_id)?,
+        _ => Vec::new(),
+    };
+    let expires_at = match state {
+        MatchState::Pending | MatchState::Accepted => config().match_expiry(row.created_at),
+        _ => None,
+    };
+    // users without a contact can only be reached in the chat
+    let twitter_handle = contacts
+        .iter()
+        .find(|c| c.channel == Channel::Twitter)
+        .map(|c| c.value.clone());
+    Ok(Some(Match {
+        id: row.id,
+        state,
+        score: row.score,
+        expires_at,
+        contacts,
+        twitter_handle,
+    }))
+}
+
+/// Expires the matches older than `match_ttl_secs` that both users haven't accepted.
+/// Returns how many expired.
+pub fn expire_stale(now: u64) -> Result<usize> {
+    if config().match_ttl_secs == 0 {
+        return Ok(0);
+    }
+    expire_matches(&connect_db()?, now.saturating_sub(config().match_ttl_secs))
+}
+
+/// Expires stale matches every `match_sweep_secs`, until the server stops
+pub async fn sweep() {
+    let mut interval = tokio::time::interval(Duration::from_secs(config().match_sweep_secs.max(1)));
+    loop {
+        interval.tick().await;
+        match expire_stale(now()) {
+            Ok(0) => {}
+            Ok(expired) => tracing::info!(expired, "expired stale matches"),
+            Err(e) => tracing::warn!("failed to expire stale matches: {e}"),
+        }
+    }
+}
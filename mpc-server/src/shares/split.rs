@@ -1,42 +1,60 @@
 use co_noir::{Bn254, Rep3MpcNet};
 use noirc_artifacts::program::ProgramArtifact;
-use rand::{Rng, distributions::Alphanumeric};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+use tempfile::NamedTempFile;
 
 use super::Share;
+use crate::config::config;
+use crate::error::{Error, Result};
+use crate::profile;
+use crate::redact::Redacted;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProverData {
-    user1: User,
+    /// Version of the profile schema, see `profile.rs`
+    #[serde(default = "profile::legacy_version")]
+    version: u32,
+    user1: Redacted<serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct User {
-    age: u32,
-    gender: u32,
-    id: String,
-    interests: Vec<u32>,
-    region: u32,
-    preferences: Preferences,
+/// Inputs of the circuit, the `User` struct of `circuit/src/structs.nr`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub age: u32,
+    pub gender: u32,
+    pub id: String,
+    pub interests: Vec<u32>,
+    pub region: u32,
+    /// Geohash cell, 0 if unknown
+    pub location: u32,
+    pub preferences: Preferences,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Preferences {
-    age_max: u32,
-    age_min: u32,
-    gender: u32,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Preferences {
+    pub age_max: u32,
+    pub age_min: u32,
+    pub gender: u32,
+    /// Distance band: same region, nearby, wide or anywhere
+    pub distance: u32,
 }
 
 pub async fn split_handler(
     payload: ProverData,
     program_artifact: &ProgramArtifact,
-) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let prover_path1 = save_prover_data(&payload, false)?;
-    let prover_path2 = save_prover_data(&payload, true)?;
-
-    let shares1 = shares_to_vec_u8(split_input(prover_path1, &program_artifact)?)?;
-    let shares2 = shares_to_vec_u8(split_input(prover_path2, &program_artifact)?)?;
+) -> Result<Vec<String>> {
+    let user =
+        profile::parse(payload.version, payload.user1.expose().clone())?.to_circuit_input()?;
+    // the prover files hold the plaintext profile, they are deleted as soon as they are dropped
+    let prover_file1 = save_prover_data(&user, false)?;
+    let prover_file2 = save_prover_data(&user, true)?;
+
+    let shares1 = split_input(prover_file1.path().to_path_buf(), &program_artifact);
+    let shares2 = split_input(prover_file2.path().to_path_buf(), &program_artifact);
+    drop((prover_file1, prover_file2));
+    let shares1 = shares_to_vec_u8(shares1?)?;
+    let shares2 = shares_to_vec_u8(shares2?)?;
 
     let mut out = shares1
         .iter()
@@ -53,11 +71,9 @@ pub async fn split_handler(
     Ok(out)
 }
 
-pub fn split_input(
-    input_path: PathBuf,
-    program_artifact: &ProgramArtifact,
-) -> Result<[Share; 3], Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let inputs = co_noir::parse_input(input_path, &program_artifact)?;
+pub fn split_input(input_path: PathBuf, program_artifact: &ProgramArtifact) -> Result<[Share; 3]> {
+    let inputs = co_noir::parse_input(input_path, &program_artifact)
+        .map_err(|e| Error::Validation(format!("Invalid prover data: {e}")))?;
 
     let mut rng = rand::thread_rng();
     let shares = co_noir::split_input_rep3::<Bn254, Rep3MpcNet, _>(inputs, &mut rng);
@@ -65,47 +81,21 @@ pub fn split_input(
     Ok(shares)
 }
 
-fn shares_to_vec_u8(
-    shares: [Share; 3],
-) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+fn shares_to_vec_u8(shares: [Share; 3]) -> Result<Vec<Vec<u8>>> {
     Ok(shares
         .iter()
         .map(|share| bincode::serialize(share))
         .collect::<Result<Vec<Vec<u8>>, _>>()?)
 }
 
-fn save_prover_data(
-    prover: &ProverData,
-    as_user2: bool,
-) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let mut toml = toml::to_string(prover)?;
-    if as_user2 {
-        toml = toml.replace("user1", "user2");
-    }
-    println!("toml: {}", toml);
-    let file_path = write_file(None, &toml)?;
-    Ok(file_path)
-}
-
-fn write_file(
-    file_name: Option<&str>,
-    data: &str,
-) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tmp");
+fn save_prover_data(user: &User, as_user2: bool) -> Result<NamedTempFile> {
+    let key = if as_user2 { "user2" } else { "user1" };
+    let toml = toml::to_string(&BTreeMap::from([(key, user)]))
+        .map_err(|e| Error::Internal(e.to_string()))?;
 
     // create the directory if it doesn't exist
-    std::fs::create_dir_all(dir.clone())?;
-
-    let name = match file_name {
-        Some(file_name) => file_name.to_string(),
-        None => rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(10)
-            .map(char::from)
-            .collect(),
-    };
-
-    let file = dir.join(name);
-    std::fs::write(file.clone(), data)?;
+    std::fs::create_dir_all(&config().tmp_dir)?;
+    let mut file = NamedTempFile::new_in(&config().tmp_dir)?;
+    file.write_all(toml.as_bytes())?;
     Ok(file)
 }
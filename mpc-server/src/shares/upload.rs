@@ -1,12 +1,24 @@
-use axum::extract::Multipart;
+use axum::{body::Bytes, extract::Multipart};
 use co_noir::Rep3AcvmType;
 use rand::{Rng, distributions::Alphanumeric};
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    db::{connect_db, insert_user},
-    matching::{SHARES_DIR_1, SHARES_DIR_2},
-    token::encode_token,
+    config::config,
+    contacts::{self, Channel, Contact, Visibility},
+    crypto,
+    db::{
+        connect_db, delete_share_version, get_share_versions, insert_contacts, insert_user,
+        reserve_share_version, set_share_version,
+    },
+    error::{Error, Result},
+    metrics::UPLOADS,
+    profile::CIRCUIT_ABI_VERSION,
+    redact::Redacted,
+    token::{TokenPair, issue_tokens},
 };
 
 const MIN_SHARE_SIZE: usize = 500;
@@ -14,27 +26,87 @@ const MAX_SHARE_SIZE: usize = 2000;
 
 pub type Share = BTreeMap<String, Rep3AcvmType<ark_bn254::Fr>>;
 
+/// Creates a user. The twitter handle is optional, more contacts can be added with
+/// `PUT /contacts`.
 pub async fn upload(
-    twitter_handle: String,
-    mut multipart: Multipart,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    twitter_handle: Option<Redacted<String>>,
+    multipart: Multipart,
+) -> Result<TokenPair> {
+    let contacts = contacts::validate(
+        twitter_handle
+            .filter(|handle| !handle.expose().is_empty())
+            .map(|handle| Contact {
+                channel: Channel::Twitter,
+                value: handle.expose().clone(),
+                visibility: Visibility::Matches,
+            })
+            .into_iter()
+            .collect(),
+    )?;
+    let shares = read_shares(multipart).await?;
+    let user_id = random_id();
+    write_shares(&user_id, 0, &shares)?;
+
+    let conn = connect_db()?;
+    // the shares come from `/split`, which uses the current ABI
+    insert_user(&conn, &user_id, CIRCUIT_ABI_VERSION)?;
+    insert_contacts(&conn, &user_id, &contacts)?;
+
+    let tokens = issue_tokens(user_id)?;
+    UPLOADS.inc();
+
+    Ok(tokens)
+}
+
+/// Uploads new shares for an existing user, after editing the profile or for a new circuit.
+/// The previous shares are kept, the user is matched again with everyone.
+pub async fn replace_shares(user_id: &str, multipart: Multipart) -> Result<u32> {
+    let shares = read_shares(multipart).await?;
+
+    let mut conn = connect_db()?;
+    let version = reserve_share_version(&conn, user_id, CIRCUIT_ABI_VERSION)?;
+    // new files, so the current version stays usable until the database points to this one
+    if let Err(e) = write_shares(user_id, version, &shares) {
+        delete_share_version(&conn, user_id, version)?;
+        return Err(e);
+    }
+    set_share_version(&mut conn, user_id, version, CIRCUIT_ABI_VERSION)?;
+
+    UPLOADS.inc();
+    Ok(version)
+}
+
+/// Goes back to shares uploaded before. Like a re-upload, the user is matched again.
+pub fn rollback_shares(user_id: &str, version: u32) -> Result<()> {
+    let mut conn = connect_db()?;
+    let share_version = get_share_versions(&conn, user_id)?
+        .into_iter()
+        .find(|v| v.version == version)
+        .ok_or(Error::NotFound("Share version"))?;
+    set_share_version(&mut conn, user_id, version, share_version.abi_version)
+}
+
+async fn read_shares(mut multipart: Multipart) -> Result<Vec<Bytes>> {
     let mut shares = Vec::new();
     while let Some(field) = multipart.next_field().await? {
         let data = field.bytes().await?;
 
         if data.len() < MIN_SHARE_SIZE || data.len() > MAX_SHARE_SIZE {
-            return Err("Invalid share size".into());
+            return Err(Error::Validation("Invalid share size".to_string()));
         }
 
         shares.push(data);
     }
 
     if shares.len() != 6 {
-        return Err("Invalid number of shares".into());
+        return Err(Error::Validation("Invalid number of shares".to_string()));
     }
+    Ok(shares)
+}
 
-    let dir1 = SHARES_DIR_1.clone();
-    let dir2 = SHARES_DIR_2.clone();
+fn write_shares(user_id: &str, version: u32, shares: &[Bytes]) -> Result<()> {
+    let dir1 = config().shares_dir_1();
+    let dir2 = config().shares_dir_2();
 
     std::fs::create_dir_all(&dir1)?;
     std::fs::create_dir_all(&dir2)?;
@@ -42,40 +114,72 @@ pub async fn upload(
     let shares1 = shares[..3].to_vec();
     let shares2 = shares[3..].to_vec();
 
-    let user_id = random_id();
-
     for (i, share) in shares1.iter().enumerate() {
-        let file_name = format!("{}-{}", user_id, i);
-        let file_path = dir1.join(file_name);
-        std::fs::write(file_path, share)?;
+        let file_path = share_path(&dir1, user_id, version, i);
+        std::fs::write(file_path, crypto::seal(share)?)?;
     }
     for (i, share) in shares2.iter().enumerate() {
-        let file_name = format!("{}-{}", user_id, i);
-        let file_path = dir2.join(file_name);
-        std::fs::write(file_path, share)?;
+        let file_path = share_path(&dir2, user_id, version, i);
+        std::fs::write(file_path, crypto::seal(share)?)?;
     }
+    Ok(())
+}
 
-    let conn = connect_db()?;
-    insert_user(&conn, &user_id, &twitter_handle)?;
-
-    let token = encode_token(user_id)?;
-
-    Ok(token)
+/// Share `i` of a version. Version 0 keeps the names from before shares were versioned.
+fn share_path(dir: &Path, id: &str, version: u32, i: usize) -> PathBuf {
+    match version {
+        0 => dir.join(format!("{id}-{i}")),
+        version => dir.join(format!("{id}-v{version}-{i}")),
+    }
 }
 
-pub fn get_shares(
-    id: &str,
-    user1: bool,
-) -> Result<[Share; 3], Box<dyn std::error::Error + Send + Sync>> {
+pub fn get_shares(id: &str, version: u32, user1: bool) -> Result<[Share; 3]> {
     let dir = if user1 {
-        SHARES_DIR_1.clone()
+        config().shares_dir_1()
     } else {
-        SHARES_DIR_2.clone()
+        config().shares_dir_2()
     };
-    let share0 = bin_to_share(std::fs::read(dir.join(format!("{id}-0")))?)?;
-    let share1 = bin_to_share(std::fs::read(dir.join(format!("{id}-1")))?)?;
-    let share2 = bin_to_share(std::fs::read(dir.join(format!("{id}-2")))?)?;
-    Ok([share0, share1, share2])
+    let share = |i| -> Result<Share> {
+        let data = std::fs::read(share_path(&dir, id, version, i))?;
+        // files from before encryption at rest stay readable until `reencrypt` seals them
+        if crypto::is_sealed(&data) {
+            bin_to_share(crypto::open(&data)?)
+        } else {
+            bin_to_share(data)
+        }
+    };
+    Ok([share(0)?, share(1)?, share(2)?])
+}
+
+/// Re-seals every share file with the current master key.
+/// Share files stored in plaintext (before encryption at rest was added) are sealed.
+pub fn reencrypt_shares() -> Result<usize> {
+    let mut count = 0;
+    for dir in [config().shares_dir_1(), config().shares_dir_2()] {
+        if !dir.exists() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_some_and(|ext| ext == "tmp") {
+                continue;
+            }
+
+            let data = std::fs::read(&path)?;
+            let sealed = if crypto::is_sealed(&data) {
+                crypto::rewrap(&data)?
+            } else {
+                crypto::seal(&data)?
+            };
+
+            // write to a temporary file first so a crash can't leave a truncated share
+            let tmp_path = path.with_extension("tmp");
+            std::fs::write(&tmp_path, sealed)?;
+            std::fs::rename(&tmp_path, &path)?;
+            count += 1;
+        }
+    }
+    Ok(count)
 }
 
 fn random_id() -> String {
@@ -86,7 +190,7 @@ fn random_id() -> String {
         .collect()
 }
 
-fn bin_to_share(bin: Vec<u8>) -> Result<Share, Box<dyn std::error::Error + Send + Sync>> {
+fn bin_to_share(bin: Vec<u8>) -> Result<Share> {
     let share: Share = bincode::deserialize(&bin)?;
     Ok(share)
 }
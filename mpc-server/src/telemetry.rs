@@ -0,0 +1,91 @@
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use tracing_subscriber::{
+    EnvFilter,
+    fmt::{self, format::FmtSpan},
+    prelude::*,
+};
+
+use crate::error::{Error, Result};
+
+// Logs and traces.
+//
+// Every match session gets a `session` span, every pair an `evaluation` span, and every MPC party
+// a `party` span, so all the events of a pair can be followed across the 3 parties.
+// With the `otel` feature, spans are also exported with OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT`
+// is set.
+
+pub fn init() -> Result<()> {
+    let fmt_layer = fmt::layer()
+        .with_target(false)
+        .with_line_number(false)
+        .with_span_events(FmtSpan::CLOSE | FmtSpan::ENTER);
+    let filter_layer = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new("info"))
+        .map_err(|e| Error::Internal(e.to_string()))?;
+
+    let registry = tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(fmt_layer);
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel::layer()?);
+
+    registry.init();
+    Ok(())
+}
+
+/// Random id of a match session
+pub fn session_id() -> String {
+    hex::encode(rand::random::<[u8; 8]>())
+}
+
+/// Mixed into the pair ids, so they can't be recomputed from the user ids. It changes on every
+/// restart, and so do the ids.
+static PAIR_SALT: Lazy<[u8; 32]> = Lazy::new(rand::random);
+
+/// Identifies a pair of users in traces without revealing who they are.
+/// The order of the users doesn't matter.
+pub fn pair_id(user1: &str, user2: &str) -> String {
+    let (a, b) = if user1 < user2 {
+        (user1, user2)
+    } else {
+        (user2, user1)
+    };
+    let hash = Sha256::new()
+        .chain_update(*PAIR_SALT)
+        .chain_update(format!("{a}:{b}"))
+        .finalize();
+    hex::encode(&hash[..8])
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+    use tracing_subscriber::{Layer, registry::LookupSpan};
+
+    use crate::error::{Error, Result};
+
+    pub fn layer<S>() -> Result<Option<impl Layer<S>>>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err() {
+            return Ok(None);
+        }
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()
+            .map_err(|e| Error::Internal(format!("OTLP exporter: {e}")))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("co-match").build())
+            .build();
+        let tracer = provider.tracer("mpc-server");
+        opentelemetry::global::set_tracer_provider(provider);
+
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+}
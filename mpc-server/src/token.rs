@@ -1,88 +1,200 @@
-use axum::{
-    Json, RequestPartsExt,
-    extract::FromRequestParts,
-    http::{StatusCode, request::Parts},
-    response::{IntoResponse, Response},
-};
+use axum::{RequestPartsExt, extract::FromRequestParts, http::request::Parts};
 use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
-use once_cell::sync::Lazy;
+use jsonwebtoken::{Header, Validation, decode, decode_header, encode, errors::ErrorKind};
+use rand::{Rng, distributions::Alphanumeric};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    db::{
+        connect_db, consume_refresh_token, get_user, insert_refresh_token, is_token_revoked,
+        purge_expired_tokens, revoke_refresh_tokens, revoke_token, user_exists,
+    },
+    error::{AuthError, Error, Result},
+    keys::keys,
+    redact::Redacted,
+};
 
-const SECRET: Lazy<String> = Lazy::new(|| std::env::var("JWT_SECRET").unwrap());
+/// Lifetime of an access token, in seconds
+const ACCESS_TOKEN_TTL: u64 = 15 * 60;
+/// Lifetime of a refresh token, in seconds
+const REFRESH_TOKEN_TTL: u64 = 30 * 24 * 60 * 60;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Token {
     pub user_id: String,
     pub exp: u64,
+    #[serde(default)]
+    pub iat: u64,
+    #[serde(default)]
+    pub jti: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    pub token: Redacted<String>,
+    pub refresh_token: Redacted<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: Option<Redacted<String>>,
+    /// An access token issued before refresh tokens existed, see `exchange_legacy_token`
+    pub legacy_token: Option<Redacted<String>>,
 }
 
-pub fn encode_token(user_id: String) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let key = EncodingKey::from_secret(SECRET.as_ref());
+pub fn encode_token(user_id: String) -> Result<String> {
+    let key = keys()?.signing_key();
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    let iat = now();
     let token = encode(
-        &Header::default(),
+        &header,
         &Token {
             user_id,
-            exp: 100000000000,
+            exp: iat + ACCESS_TOKEN_TTL,
+            iat,
+            jti: random_string(16),
         },
-        &key,
+        &key.encoding,
     )?;
     Ok(token)
 }
 
-// pub fn decode_token(token: String) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-//     let key = DecodingKey::from_secret(SECRET.as_ref());
-//     let token = decode::<Token>(&token, &key, &Validation::default())?;
-//     Ok(token.claims.user_id)
-// }
-
-#[derive(Debug)]
-pub enum AuthError {
-    // WrongCredentials,
-    // MissingCredentials,
-    // TokenCreation,
-    InvalidToken,
+/// Issues a new access token, and a refresh token stored server-side.
+pub fn issue_tokens(user_id: String) -> Result<TokenPair> {
+    let conn = connect_db()?;
+    purge_expired_tokens(&conn, now())?;
+
+    let refresh_token = random_string(43);
+    insert_refresh_token(
+        &conn,
+        &hash_refresh_token(&refresh_token),
+        &user_id,
+        now() + REFRESH_TOKEN_TTL,
+    )?;
+
+    Ok(TokenPair {
+        token: encode_token(user_id)?.into(),
+        refresh_token: refresh_token.into(),
+    })
+}
+
+/// Exchanges a refresh token for a new token pair. The refresh token can only be used once.
+pub fn refresh(request: &RefreshRequest) -> Result<TokenPair> {
+    let refresh_token = match (&request.refresh_token, &request.legacy_token) {
+        (Some(refresh_token), _) => refresh_token.expose(),
+        (None, Some(legacy_token)) => return exchange_legacy_token(legacy_token.expose()),
+        (None, None) => return Err(AuthError::MissingToken.into()),
+    };
+    let conn = connect_db()?;
+    let user_id = consume_refresh_token(&conn, &hash_refresh_token(refresh_token), now())?;
+    issue_tokens(user_id)
+}
+
+/// Tokens issued before expiry was introduced are signed with `JWT_SECRET`, never expire and have
+/// no jti, so `verify` rejects them. Each can be exchanged once for a token pair, and is then
+/// remembered as revoked for good.
+fn exchange_legacy_token(token: &str) -> Result<TokenPair> {
+    let header = decode_header(token).map_err(|_| AuthError::InvalidToken)?;
+    if header.kid.is_some() {
+        return Err(AuthError::InvalidToken.into());
+    }
+    let key = keys()?
+        .verification_key(None)
+        .ok_or(AuthError::InvalidToken)?;
+    let claims = decode::<Token>(token, &key.decoding, &Validation::new(key.algorithm))
+        .map_err(|_| AuthError::InvalidToken)?
+        .claims;
+    if !claims.jti.is_empty() {
+        return Err(AuthError::InvalidToken.into());
+    }
+
+    let conn = connect_db()?;
+    get_user(&conn, &claims.user_id).map_err(|_| AuthError::InvalidToken)?;
+    // the token has no jti, its hash stands for it
+    let jti = format!("legacy:{}", hex::encode(Sha256::digest(token.as_bytes())));
+    if !revoke_token(&conn, &jti, claims.exp)? {
+        return Err(AuthError::RevokedToken.into());
+    }
+    tracing::info!("legacy token exchanged");
+    issue_tokens(claims.user_id)
+}
+
+/// Revokes the access token and every refresh token of the user.
+pub fn logout(token: &Token) -> Result<()> {
+    let conn = connect_db()?;
+    revoke_token(&conn, &token.jti, token.exp)?;
+    revoke_refresh_tokens(&conn, &token.user_id)?;
+    Ok(())
 }
 
-impl IntoResponse for AuthError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            // AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Wrong credentials"),
-            // AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
-            // AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
-            AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
-        };
-        let body: Json<_> = Json(json!({
-            "error": error_message,
-        }));
-        (status, body).into_response()
+/// Checks an access token: signature, expiry, revocation and that its user still exists
+pub fn verify(token: &str) -> Result<Token> {
+    // Pick the verification key from the kid
+    let header = decode_header(token).map_err(|_| AuthError::InvalidToken)?;
+    let key = keys()?
+        .verification_key(header.kid.as_deref())
+        .ok_or(AuthError::InvalidToken)?;
+    // Decode the user data
+    let token_data = decode::<Token>(token, &key.decoding, &Validation::new(key.algorithm))
+        .map_err(|e| match e.kind() {
+            ErrorKind::ExpiredSignature => AuthError::ExpiredToken,
+            _ => AuthError::InvalidToken,
+        })?;
+
+    // Tokens issued before expiry was introduced have no jti, and can't be revoked: they have to
+    // be exchanged on `/auth/refresh`, see `exchange_legacy_token`
+    if token_data.claims.jti.is_empty() {
+        return Err(AuthError::InvalidToken.into());
+    }
+    let conn = connect_db()?;
+    if is_token_revoked(&conn, &token_data.claims.jti)? {
+        return Err(AuthError::RevokedToken.into());
+    }
+    // deleting an account only revokes the token used for it, the other ones die with the user
+    if !user_exists(&conn, &token_data.claims.user_id)? {
+        return Err(AuthError::RevokedToken.into());
     }
+
+    Ok(token_data.claims)
+}
+
+fn hash_refresh_token(refresh_token: &str) -> String {
+    hex::encode(Sha256::digest(refresh_token.as_bytes()))
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_secs()
 }
 
 impl<S> FromRequestParts<S> for Token
 where
     S: Send + Sync,
 {
-    type Rejection = AuthError;
+    type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
         // Extract the token from the authorization header
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
-            .map_err(|_| AuthError::InvalidToken)?;
-        // Decode the user data
-        let token_data = decode::<Self>(
-            bearer.token(),
-            &DecodingKey::from_secret(SECRET.as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|_| AuthError::InvalidToken)?;
-
-        Ok(token_data.claims)
+            .map_err(|_| AuthError::MissingToken)?;
+        verify(bearer.token())
     }
 }
@@ -0,0 +1,170 @@
+mod common;
+
+use jsonwebtoken::{Header, Validation, decode, decode_header, encode};
+use serde_json::{Value, json};
+
+use common::profile;
+use mpc_server::{
+    account::DeletionReceipt,
+    config::config,
+    db::{connect_db, get_user},
+    keys::keys,
+    token::verify,
+};
+
+#[tokio::test]
+async fn test_delete_account() {
+    let server = common::server();
+    let client = server.client();
+
+    let alice = profile(30, 0, 1, [2, 4, 6], (25, 35), 1);
+    let bob = profile(32, 1, 1, [1, 2, 3], (25, 35), 0);
+    let alice_tokens = server.upload("alice", &alice).await;
+    let bob_tokens = server.upload("bob", &bob).await;
+    // a second version of the shares, both have to be deleted
+    server.replace_shares(&alice_tokens, &alice).await;
+    server.run_matches(&alice_tokens).await;
+    assert_eq!(server.match_list(&bob_tokens).await.len(), 1);
+    // a second session, as from another device
+    let other_session: common::Tokens = client
+        .post(format!("{}/auth/refresh", server.url))
+        .json(&json!({"refresh_token": alice_tokens.refresh_token}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let response: Value = client
+        .delete(format!("{}/account", server.url))
+        .bearer_auth(&alice_tokens.token)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // the receipt is signed with the token keys
+    let receipt = response["receipt"].as_str().unwrap();
+    let header = decode_header(receipt).unwrap();
+    let key = keys()
+        .unwrap()
+        .verification_key(header.kid.as_deref())
+        .unwrap();
+    let mut validation = Validation::new(key.algorithm);
+    validation.required_spec_claims.clear();
+    validation.validate_exp = false;
+    let receipt = decode::<DeletionReceipt>(receipt, &key.decoding, &validation)
+        .unwrap()
+        .claims;
+    let alice_id = receipt.sub;
+    assert_eq!(receipt.typ, "deletion_receipt");
+    assert_eq!(receipt.share_files, 12);
+    assert_eq!(receipt.matches, 1);
+
+    // nothing left on disk or in the database
+    for dir in [config().shares_dir_1(), config().shares_dir_2()] {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            assert!(!name.starts_with(&alice_id), "{name} was not deleted");
+        }
+    }
+    let conn = connect_db().unwrap();
+    assert!(get_user(&conn, &alice_id).is_err());
+    for (table, column) in [
+        ("matches", "user_id1"),
+        ("matches", "user_id2"),
+        ("share_versions", "user_id"),
+        ("refresh_tokens", "user_id"),
+        ("users", "checked"),
+    ] {
+        let count: u32 = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM {table} WHERE {column} LIKE '%' || ?1 || '%'"),
+                [&alice_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 0, "{table}.{column}");
+    }
+
+    // bob lost the match and was told, alice's token is revoked
+    assert!(server.matches(&bob_tokens).await.is_empty());
+    let notices: Value = client
+        .get(format!("{}/notices", server.url))
+        .bearer_auth(&bob_tokens.token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(notices["notices"][0]["kind"], "match_deleted");
+    let status = client
+        .get(format!("{}/profile", server.url))
+        .bearer_auth(&alice_tokens.token)
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 401);
+
+    // so is every other token of alice: the account can't be written to again
+    let contacts = client
+        .put(format!("{}/contacts", server.url))
+        .bearer_auth(&other_session.token)
+        .json(&json!({"contacts": [{"channel": "email", "value": "alice@example.org"}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(contacts.status(), 401);
+}
+
+#[tokio::test]
+async fn test_legacy_token_exchange() {
+    let server = common::server();
+    let tokens = server
+        .upload("dave", &profile(40, 1, 1, [2, 3, 4], (30, 45), 2))
+        .await;
+    let user_id = verify(&tokens.token).unwrap().user_id;
+
+    // signed with JWT_SECRET, without kid nor jti, like the tokens of the first versions
+    let legacy_key = keys().unwrap().verification_key(None).unwrap();
+    let legacy_token = encode(
+        &Header::default(),
+        &json!({"user_id": user_id, "exp": 100000000000u64}),
+        &legacy_key.encoding,
+    )
+    .unwrap();
+    assert!(verify(&legacy_token).is_err());
+
+    let exchange = |body: Value| async move {
+        server
+            .client()
+            .post(format!("{}/auth/refresh", server.url))
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    };
+    let exchanged: common::Tokens = exchange(json!({"legacy_token": legacy_token}))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(verify(&exchanged.token).unwrap().user_id, user_id);
+
+    // only once, and only for legacy tokens
+    let again = exchange(json!({"legacy_token": legacy_token})).await;
+    assert_eq!(again.status(), 401);
+    let current = exchange(json!({"legacy_token": exchanged.token})).await;
+    assert_eq!(current.status(), 401);
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use serde_json::{Value, json};
+
+use common::{next_json, profile};
+
+#[tokio::test]
+async fn test_chat() {
+    let server = common::server();
+    let client = server.client();
+
+    let alice = server
+        .upload("alice", &profile(30, 0, 1, [2, 4, 6], (25, 35), 1))
+        .await;
+    let bob = server
+        .upload("bob", &profile(32, 1, 1, [1, 2, 3], (25, 35), 0))
+        .await;
+    // no twitter handle, carol can only be reached in the chat
+    let carol_shares = server
+        .split(&profile(33, 1, 1, [4, 6, 8], (25, 35), 0))
+        .await;
+    let carol = server.upload_shares("", &carol_shares).await;
+    server.run_matches(&alice).await;
+
+    let matches = server.match_list(&alice).await;
+    let (carol_match, bob_match) = (
+        matches[0]["id"].as_u64().unwrap(),
+        matches[1]["id"].as_u64().unwrap(),
+    );
+    let url = |id: u64, path: &str| format!("{}/matches/{id}/{path}", server.url);
+
+    // the chat opens once the match is revealed
+    let message = json!({"ciphertext": "aGVsbG8="});
+    let early = client
+        .post(url(bob_match, "messages"))
+        .bearer_auth(&alice.token)
+        .json(&message)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(early.status(), 409);
+    for tokens in [&alice, &bob, &carol] {
+        server.accept_all(tokens).await;
+    }
+
+    // key exchange
+    for (tokens, key) in [(&alice, "YWxpY2U="), (&bob, "Ym9i")] {
+        client
+            .put(url(bob_match, "key"))
+            .bearer_auth(&tokens.token)
+            .json(&json!({ "public_key": key }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    let keys: Value = client
+        .get(url(bob_match, "keys"))
+        .bearer_auth(&bob.token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(keys, json!({"mine": "Ym9i", "theirs": "YWxpY2U="}));
+
+    // delivered over the socket, stored as sent
+    let ciphertexts = ["bWVzc2FnZTA=", "bWVzc2FnZTE=", "bWVzc2FnZTI="];
+    let mut bob_socket = server.chat_socket(&bob).await;
+    for ciphertext in ciphertexts {
+        client
+            .post(url(bob_match, "messages"))
+            .bearer_auth(&alice.token)
+            .json(&json!({ "ciphertext": ciphertext }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        let delivered = next_json(&mut bob_socket).await;
+        assert_eq!(delivered["ciphertext"], ciphertext);
+        assert_eq!(delivered["mine"], false);
+    }
+    bob_socket.close(None).await.unwrap();
+
+    // newest first, paginated
+    let first: Value = client
+        .get(url(bob_match, "messages"))
+        .bearer_auth(&bob.token)
+        .query(&[("limit", 2)])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let first = first["messages"].as_array().unwrap();
+    assert_eq!(first.len(), 2);
+    assert_eq!(first[0]["ciphertext"], ciphertexts[2]);
+    let second: Value = client
+        .get(url(bob_match, "messages"))
+        .bearer_auth(&alice.token)
+        .query(&[("limit", 2), ("before", first[1]["id"].as_i64().unwrap())])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let second = second["messages"].as_array().unwrap();
+    assert_eq!(second.len(), 1);
+    assert_eq!(second[0]["ciphertext"], ciphertexts[0]);
+    assert_eq!(second[0]["mine"], true);
+
+    // carol has no handle, and can't read alice and bob's conversation
+    let carol_matches = server.match_list(&carol).await;
+    assert_eq!(carol_matches[0]["id"].as_u64().unwrap(), carol_match);
+    assert_eq!(carol_matches[0]["state"], "revealed");
+    assert!(carol_matches[0].get("twitter_handle").is_some());
+    assert!(
+        server.match_list(&alice).await[0]
+            .get("twitter_handle")
+            .is_none()
+    );
+    let denied = client
+        .get(url(bob_match, "messages"))
+        .bearer_auth(&carol.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(denied.status(), 404);
+
+    // the socket needs a valid token
+    let mut socket = server.connect_chat("not a token").await;
+    assert_eq!(next_json(&mut socket).await["code"], "invalid_token");
+}
+
+#[tokio::test]
+async fn test_chat_socket_closes_on_logout() {
+    let server = common::server();
+    let dave = server
+        .upload("dave", &profile(40, 1, 1, [2, 3, 4], (30, 45), 2))
+        .await;
+    let mut socket = server.chat_socket(&dave).await;
+
+    server
+        .client()
+        .post(format!("{}/auth/logout", server.url))
+        .bearer_auth(&dave.token)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(next_json(&mut socket).await["code"], "revoked_token");
+}
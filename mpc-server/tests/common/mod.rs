@@ -0,0 +1,433 @@
+// Test harness: boots the server in-process, on a random port, with ephemeral certificates.
+//
+// The server only needs the CRS from `config/` (see `config.sh`), everything else lives in a
+// temporary directory. It is started once per test binary, since the config and the
+// certificates are global.
+#![allow(dead_code)]
+
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use futures_util::{SinkExt, StreamExt};
+use rcgen::CertifiedKey;
+use reqwest::multipart::{Form, Part};
+use rustls::pki_types::{CertificateDer, pem::PemObject};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream, tungstenite::Message};
+
+use mpc_server::{
+    app::{self, Circuit},
+    certs,
+    config::{self, Config, DeletionNotice, MpcConfig, config},
+    db::setup_db,
+    error::Error,
+    geohash,
+    matching::run_match,
+    metrics,
+    shares::{Preferences, Share, User, split_input},
+};
+
+static SERVER: OnceLock<TestServer> = OnceLock::new();
+/// Pairs evaluated with `run_match` all use the ports of thread 0
+static MPC_PORTS: Mutex<()> = Mutex::new(());
+
+/// Bearer token of the admin API
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+pub struct TestServer {
+    pub url: String,
+    tls_cert: String,
+    pub circuit: Arc<Circuit>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Tokens {
+    pub token: String,
+    pub refresh_token: String,
+}
+
+/// Starts the server on its own thread and runtime, so it outlives the runtime of each test
+pub fn server() -> &'static TestServer {
+    SERVER.get_or_init(start)
+}
+
+fn start() -> TestServer {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let root = std::env::temp_dir().join(format!("comatch-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let data_dir = root.join("data");
+    let config_dir = root.join("config");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::create_dir_all(&config_dir).unwrap();
+
+    std::fs::copy(
+        manifest_dir.join("data/circuit.json"),
+        data_dir.join("circuit.json"),
+    )
+    .unwrap();
+    for crs in ["bn254_g1.dat", "bn254_g2.dat"] {
+        std::fs::copy(manifest_dir.join("config").join(crs), config_dir.join(crs))
+            .unwrap_or_else(|_| panic!("config/{crs} is missing, run config.sh"));
+    }
+
+    for i in 0..3 {
+        let CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(config_dir.join(format!("cert{i}.der")), cert.der()).unwrap();
+        std::fs::write(
+            config_dir.join(format!("key{i}.der")),
+            key_pair.serialize_der(),
+        )
+        .unwrap();
+    }
+    let CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let tls_cert = cert.pem();
+    let tls_key = key_pair.serialize_pem();
+
+    // SAFETY: set before the server thread starts, nothing else reads the environment yet
+    unsafe {
+        std::env::set_var("MASTER_KEY", hex::encode(rand::random::<[u8; 32]>()));
+        std::env::set_var("JWT_SECRET", hex::encode(rand::random::<[u8; 32]>()));
+        std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    }
+    config::init(Config {
+        bind: ([127, 0, 0, 1], 0).into(),
+        data_dir,
+        config_dir,
+        ssl_dir: root.join("ssl-cert"),
+        tmp_dir: root.join("tmp"),
+        deletion_notice: DeletionNotice::Notify,
+        chat_check_secs: 1,
+        mpc: MpcConfig {
+            party_ports: [31000, 32000, 33000],
+            ..MpcConfig::default()
+        },
+        ..Config::default()
+    })
+    .unwrap();
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    metrics::init();
+    setup_db().unwrap();
+    certs::init().unwrap();
+    let circuit = Arc::new(Circuit::load().unwrap());
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let cert_pem = tls_cert.clone();
+    let server_circuit = circuit.clone();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let app = app::router(server_circuit);
+
+            let tls_config = RustlsConfig::from_pem(cert_pem.into_bytes(), tls_key.into_bytes())
+                .await
+                .unwrap();
+            let handle = Handle::new();
+            let server = axum_server::bind_rustls(config().bind, tls_config)
+                .handle(handle.clone())
+                .serve(app.into_make_service());
+            let server = tokio::spawn(server);
+            let addr = handle.listening().await.expect("server failed to start");
+            tx.send(addr.port()).unwrap();
+            server.await.unwrap().unwrap();
+        });
+    });
+    let port = rx.recv().expect("server failed to start");
+
+    TestServer {
+        url: format!("https://localhost:{port}"),
+        tls_cert,
+        circuit,
+    }
+}
+
+impl TestServer {
+    /// A client trusting the server certificate. Each test has its own runtime, so each test
+    /// needs its own client.
+    pub fn client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(self.tls_cert.as_bytes()).unwrap())
+            .build()
+            .unwrap()
+    }
+
+    /// The chat WebSocket of the user, authenticated and ready
+    pub async fn chat_socket(&self, tokens: &Tokens) -> ChatSocket {
+        let mut socket = self.connect_chat(&tokens.token).await;
+        assert_eq!(next_json(&mut socket).await, json!({"ready": true}));
+        socket
+    }
+
+    /// Opens the chat WebSocket and sends the token
+    pub async fn connect_chat(&self, token: &str) -> ChatSocket {
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_slice(self.tls_cert.as_bytes()).unwrap())
+            .unwrap();
+        let tls = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let (mut socket, _) = tokio_tungstenite::connect_async_tls_with_config(
+            format!("{}/chat", self.url.replace("https", "wss")),
+            None,
+            false,
+            Some(Connector::Rustls(Arc::new(tls))),
+        )
+        .await
+        .unwrap();
+        socket.send(Message::text(token)).await.unwrap();
+        socket
+    }
+
+    /// Splits the profile and uploads the shares, like the web app does
+    pub async fn upload(&self, twitter_handle: &str, user: &User) -> Tokens {
+        let shares = self.split(user).await;
+        self.upload_shares(twitter_handle, &shares).await
+    }
+
+    /// The hex encoded shares of a profile: 3 as user1, then 3 as user2
+    pub async fn split(&self, user: &User) -> Vec<String> {
+        let split: Value = self
+            .client()
+            .post(format!("{}/split", self.url))
+            .json(&json!({ "user1": user }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        serde_json::from_value(split["shares"].clone()).unwrap()
+    }
+
+    pub async fn upload_shares(&self, twitter_handle: &str, shares: &[String]) -> Tokens {
+        self.client()
+            .post(format!("{}/upload", self.url))
+            .query(&[("twitter_handle", twitter_handle)])
+            .multipart(shares_form(shares))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    /// Splits the profile and replaces the shares of the user, returns the new version
+    pub async fn replace_shares(&self, tokens: &Tokens, user: &User) -> u32 {
+        let shares = self.split(user).await;
+        let response: Value = self
+            .client()
+            .put(format!("{}/profile/shares", self.url))
+            .bearer_auth(&tokens.token)
+            .multipart(shares_form(&shares))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        response["version"].as_u64().unwrap() as u32
+    }
+
+    pub async fn run_matches(&self, tokens: &Tokens) {
+        self.client()
+            .post(format!("{}/matches", self.url))
+            .bearer_auth(&tokens.token)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    /// Runs the circuit on a pair, without going through the database.
+    /// Returns the score if the proof was generated and verified.
+    pub fn evaluate(&self, user1: &User, user2: &User) -> Option<u32> {
+        match self.run_match(self.shares(user1, user2)) {
+            Ok(score) => Some(score),
+            // the circuit assertions failed
+            Err(Error::NoMatch) => None,
+            Err(e) => panic!("match failed: {e}"),
+        }
+    }
+
+    /// Input shares of a pair. Unlike `/split`, the profiles aren't validated, so the circuit
+    /// gets them as they are.
+    pub fn shares(&self, user1: &User, user2: &User) -> [Share; 3] {
+        let prover_path = config()
+            .tmp_dir
+            .join(format!("{}.toml", hex::encode(rand::random::<[u8; 8]>())));
+        std::fs::create_dir_all(&config().tmp_dir).unwrap();
+        std::fs::write(
+            &prover_path,
+            toml::to_string(&json!({ "user1": user1, "user2": user2 })).unwrap(),
+        )
+        .unwrap();
+        let shares = split_input(prover_path.clone(), &self.circuit.program_artifact).unwrap();
+        std::fs::remove_file(prover_path).unwrap();
+        shares
+    }
+
+    /// `run_match` with the test certificates and circuit
+    pub fn run_match(&self, shares: [Share; 3]) -> mpc_server::error::Result<u32> {
+        let _ports = MPC_PORTS.lock().unwrap_or_else(|e| e.into_inner());
+        run_match(
+            0,
+            shares,
+            &*certs::current()?,
+            &self.circuit.program_artifact,
+            self.circuit.constraint_system.clone(),
+            self.circuit.recursive,
+            self.circuit.has_zk,
+            self.circuit.prover_crs.clone(),
+            self.circuit.verifier_crs.clone(),
+        )
+    }
+
+    pub async fn set_min_score(&self, tokens: &Tokens, min_score: u32) {
+        self.client()
+            .put(format!("{}/profile/min_score", self.url))
+            .bearer_auth(&tokens.token)
+            .json(&json!({ "min_score": min_score }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    /// Active matches of the user, revealed or not, best first
+    pub async fn match_list(&self, tokens: &Tokens) -> Vec<Value> {
+        self.match_list_in(tokens, "active").await
+    }
+
+    /// Matches of the user with a `GET /matches` state filter
+    pub async fn match_list_in(&self, tokens: &Tokens, state: &str) -> Vec<Value> {
+        let response: Value = self
+            .client()
+            .get(format!("{}/matches?state={state}", self.url))
+            .bearer_auth(&tokens.token)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        response["matches"].as_array().unwrap().clone()
+    }
+
+    /// Twitter handles and scores of the revealed matches, best first
+    pub async fn matches(&self, tokens: &Tokens) -> Vec<(String, u32)> {
+        self.match_list(tokens)
+            .await
+            .iter()
+            .filter(|m| m["state"] == "revealed")
+            .map(|m| {
+                (
+                    m["twitter_handle"].as_str().unwrap().to_string(),
+                    m["score"].as_u64().unwrap() as u32,
+                )
+            })
+            .collect()
+    }
+
+    /// `accept` or `decline` a match
+    pub async fn respond(&self, tokens: &Tokens, match_id: u64, action: &str) -> reqwest::Response {
+        self.client()
+            .post(format!("{}/matches/{match_id}/{action}", self.url))
+            .bearer_auth(&tokens.token)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Accepts every match of the user which isn't revealed yet
+    pub async fn accept_all(&self, tokens: &Tokens) {
+        for m in self.match_list(tokens).await {
+            if m["state"] == "pending" {
+                let id = m["id"].as_u64().unwrap();
+                self.respond(tokens, id, "accept")
+                    .await
+                    .error_for_status()
+                    .unwrap();
+            }
+        }
+    }
+}
+
+/// Sets the location and the distance band of a profile
+pub fn located(mut user: User, geohash: &str, distance: u32) -> User {
+    user.location = geohash::circuit_cell(geohash::parse(geohash).unwrap());
+    user.preferences.distance = distance;
+    user
+}
+
+pub type ChatSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// The next text message on the socket, as JSON
+pub async fn next_json(socket: &mut ChatSocket) -> Value {
+    let message = tokio::time::timeout(Duration::from_secs(10), socket.next())
+        .await
+        .expect("no message on the socket")
+        .unwrap()
+        .unwrap();
+    serde_json::from_str(message.to_text().unwrap()).unwrap()
+}
+
+fn shares_form(shares: &[String]) -> Form {
+    let mut form = Form::new();
+    for (i, share) in shares.iter().enumerate() {
+        form = form.part(
+            format!("share{i}"),
+            Part::bytes(hex::decode(share).unwrap()).file_name(format!("share{i}")),
+        );
+    }
+    form
+}
+
+/// A share returned by `/split`
+pub fn decode_share(share: &str) -> Share {
+    bincode::deserialize(&hex::decode(share).unwrap()).unwrap()
+}
+
+/// A profile with a random id, matched by region
+pub fn profile(
+    age: u32,
+    gender: u32,
+    region: u32,
+    interests: [u32; 3],
+    (age_min, age_max): (u32, u32),
+    preferred_gender: u32,
+) -> User {
+    User {
+        age,
+        gender,
+        // the id is a field element, keep it below the modulus
+        id: format!("0x00{}", hex::encode(rand::random::<[u8; 31]>())),
+        interests: interests.to_vec(),
+        region,
+        location: 0,
+        preferences: Preferences {
+            age_max,
+            age_min,
+            gender: preferred_gender,
+            distance: 0,
+        },
+    }
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use mpc_server::contacts::{Channel, Contact, Visibility, validate};
+use serde_json::{Value, json};
+
+use common::profile;
+
+#[test]
+fn test_normalize() {
+    let cases = [
+        (Channel::Twitter, "@alice_01", Some("alice_01")),
+        (Channel::Twitter, "alice_in_wonderland", None),
+        (Channel::Twitter, "al ice", None),
+        (Channel::Telegram, "@alice", Some("alice")),
+        (Channel::Telegram, "ali", None),
+        (Channel::Telegram, "1alice", None),
+        (Channel::Signal, "alice.42", Some("alice.42")),
+        (Channel::Signal, "+41791234567", Some("+41791234567")),
+        (Channel::Signal, "alice", None),
+        (Channel::Signal, "+41 79", None),
+        (
+            Channel::Email,
+            " Alice@Example.org ",
+            Some("alice@example.org"),
+        ),
+        (Channel::Email, "alice@localhost", None),
+        (Channel::Email, "alice@-example.org", None),
+        (Channel::Farcaster, "@Alice", Some("alice")),
+        (Channel::Farcaster, "alice.eth", Some("alice.eth")),
+        (Channel::Farcaster, "alice_", None),
+        (
+            Channel::Nostr,
+            "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6",
+            Some("npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6"),
+        ),
+        // last character changed, the checksum doesn't match
+        (
+            Channel::Nostr,
+            "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w7",
+            None,
+        ),
+        (Channel::Nostr, "npub1", None),
+    ];
+    for (channel, value, expected) in cases {
+        assert_eq!(
+            channel.normalize(value).as_deref(),
+            expected,
+            "{channel:?} {value}"
+        );
+    }
+}
+
+#[test]
+fn test_validate() {
+    let contact = |channel, value: &str| Contact {
+        channel,
+        value: value.to_string(),
+        visibility: Visibility::Matches,
+    };
+
+    let contacts = validate(vec![
+        contact(Channel::Twitter, "@alice"),
+        contact(Channel::Email, "alice@example.org"),
+    ])
+    .unwrap();
+    assert_eq!(contacts[0].value, "alice");
+
+    // duplicates are detected after normalization
+    assert!(
+        validate(vec![
+            contact(Channel::Twitter, "@alice"),
+            contact(Channel::Twitter, "alice"),
+        ])
+        .is_err()
+    );
+    // the same value on another channel is fine
+    assert!(
+        validate(vec![
+            contact(Channel::Twitter, "alice"),
+            contact(Channel::Farcaster, "alice"),
+        ])
+        .is_ok()
+    );
+    assert!(validate(vec![contact(Channel::Telegram, "ali")]).is_err());
+    assert!(
+        validate(
+            (0..11)
+                .map(|i| contact(Channel::Email, &format!("alice{i}@example.org")))
+                .collect()
+        )
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_shared_contacts() {
+    let server = common::server();
+
+    let alice = server
+        .upload("alice", &profile(30, 0, 1, [2, 4, 6], (25, 35), 1))
+        .await;
+    let bob = server
+        .upload("bob", &profile(32, 1, 1, [2, 4, 6], (25, 35), 0))
+        .await;
+
+    let set_contacts = |token: String, contacts: Value| async move {
+        server
+            .client()
+            .put(format!("{}/contacts", server.url))
+            .bearer_auth(token)
+            .json(&json!({ "contacts": contacts }))
+            .send()
+            .await
+            .unwrap()
+    };
+    let response = set_contacts(
+        alice.token.clone(),
+        json!([{"channel": "telegram", "value": "ali"}]),
+    )
+    .await;
+    assert_eq!(response.status(), 400);
+    set_contacts(
+        alice.token.clone(),
+        json!([
+            {"channel": "telegram", "value": "@alice_tg"},
+            {"channel": "email", "value": "alice@example.org", "visibility": "hidden"},
+        ]),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let contacts: Value = server
+        .client()
+        .get(format!("{}/contacts", server.url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(contacts["contacts"].as_array().unwrap().len(), 2);
+    assert_eq!(contacts["contacts"][0]["value"], "alice_tg");
+
+    server.run_matches(&alice).await;
+    assert_eq!(server.match_list(&bob).await[0]["contacts"], json!([]));
+    server.accept_all(&alice).await;
+    server.accept_all(&bob).await;
+
+    // only the contacts alice shares with matches, the twitter handle was replaced
+    let bob_matches = server.match_list(&bob).await;
+    assert_eq!(
+        bob_matches[0]["contacts"],
+        json!([{"channel": "telegram", "value": "alice_tg"}])
+    );
+    assert!(bob_matches[0].get("twitter_handle").is_none());
+    assert_eq!(
+        server.match_list(&alice).await[0]["contacts"],
+        json!([{"channel": "twitter", "value": "bob"}])
+    );
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::profile;
+
+#[tokio::test]
+async fn test_upload_and_match() {
+    let server = common::server();
+
+    let health = server
+        .client()
+        .get(format!("{}/readyz", server.url))
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        health.status().is_success(),
+        "{}",
+        health.text().await.unwrap()
+    );
+
+    // all in region 1
+    let users = [
+        // alice and bob like each other
+        ("alice", profile(30, 0, 1, [2, 4, 6], (25, 35), 1)),
+        ("bob", profile(32, 1, 1, [1, 2, 3], (25, 35), 0)),
+        // carol is open to any gender, and shares interest 7 with erin
+        ("carol", profile(28, 1, 1, [5, 7, 8], (25, 35), 2)),
+        ("erin", profile(29, 0, 1, [7, 1, 1], (25, 30), 1)),
+        // dave is too old for everyone else
+        ("dave", profile(40, 1, 1, [2, 3, 4], (30, 45), 2)),
+    ];
+
+    let mut tokens = Vec::new();
+    for (handle, profile) in users {
+        tokens.push((handle, server.upload(handle, &profile).await));
+    }
+    for (_, tokens) in &tokens {
+        server.run_matches(tokens).await;
+    }
+    for (_, tokens) in &tokens {
+        server.accept_all(tokens).await;
+    }
+
+    let expected: [(&str, &[(&str, u32)]); 5] = [
+        ("alice", &[("bob", 1)]),
+        ("bob", &[("alice", 1)]),
+        ("carol", &[("erin", 1)]),
+        ("erin", &[("carol", 1)]),
+        ("dave", &[]),
+    ];
+    for ((handle, tokens), (expected_handle, expected_matches)) in tokens.iter().zip(expected) {
+        assert_eq!(*handle, expected_handle);
+        let expected_matches: Vec<(String, u32)> = expected_matches
+            .iter()
+            .map(|(handle, score)| (handle.to_string(), *score))
+            .collect();
+        assert_eq!(
+            server.matches(tokens).await,
+            expected_matches,
+            "matches of {handle}"
+        );
+    }
+
+    // alice and carol pass the hard filters without any interest in common,
+    // their match is only revealed once both accept a score of 0
+    let (alice, carol) = (&tokens[0].1, &tokens[2].1);
+    server.set_min_score(carol, 0).await;
+    assert_eq!(server.matches(carol).await, [("erin".to_string(), 1)]);
+    server.set_min_score(alice, 0).await;
+    server.accept_all(alice).await;
+    server.accept_all(carol).await;
+    assert_eq!(
+        server.matches(alice).await,
+        [("bob".to_string(), 1), ("carol".to_string(), 0)]
+    );
+}
@@ -0,0 +1,254 @@
+// Checks that the MPC pipeline and the plaintext predicate (`predicate.rs`) agree on the rules and
+// the score of the circuit, on known vectors and on random profiles.
+mod common;
+
+use mpc_server::{
+    predicate::score,
+    shares::{Preferences, User},
+};
+use proptest::prelude::*;
+
+use common::{located, profile};
+
+struct Vector {
+    name: &'static str,
+    user1: User,
+    user2: User,
+    /// `None` when the circuit rejects the pair
+    expected: Option<u32>,
+}
+
+fn vector(name: &'static str, user1: User, user2: User, expected: Option<u32>) -> Vector {
+    Vector {
+        name,
+        user1,
+        user2,
+        expected,
+    }
+}
+
+fn vectors() -> Vec<Vector> {
+    // a zk researcher and a security researcher looking for each other
+    let alice = || profile(30, 0, 1, [2, 4, 6], (25, 35), 1);
+    let bob = || profile(32, 1, 1, [1, 2, 3], (25, 35), 0);
+    let same_id = {
+        let user = alice();
+        let mut other = bob();
+        other.id = user.id.clone();
+        (user, other)
+    };
+
+    vec![
+        vector("mutual match", alice(), bob(), Some(1)),
+        vector(
+            "age equal to age_min",
+            alice(),
+            profile(25, 1, 1, [2, 3, 4], (25, 35), 0),
+            Some(2),
+        ),
+        vector(
+            "age equal to age_max",
+            alice(),
+            profile(35, 1, 1, [2, 3, 4], (25, 35), 0),
+            Some(2),
+        ),
+        vector(
+            "age below age_min",
+            alice(),
+            profile(24, 1, 1, [2, 3, 4], (20, 35), 0),
+            None,
+        ),
+        vector(
+            "age above age_max",
+            alice(),
+            profile(36, 1, 1, [2, 3, 4], (25, 40), 0),
+            None,
+        ),
+        vector(
+            "only one side accepts the age",
+            alice(),
+            profile(32, 1, 1, [2, 3, 4], (18, 29), 0),
+            None,
+        ),
+        vector(
+            "both accept any gender",
+            profile(30, 0, 1, [2, 4, 6], (25, 35), 2),
+            profile(32, 0, 1, [1, 2, 3], (25, 35), 2),
+            Some(1),
+        ),
+        vector(
+            "any gender on one side only",
+            profile(30, 0, 1, [2, 4, 6], (25, 35), 2),
+            profile(32, 1, 1, [1, 2, 3], (25, 35), 1),
+            None,
+        ),
+        vector(
+            "same gender",
+            profile(30, 1, 1, [2, 4, 6], (25, 35), 1),
+            profile(32, 1, 1, [1, 2, 3], (25, 35), 1),
+            Some(1),
+        ),
+        vector(
+            "gender out of range",
+            alice(),
+            profile(32, 2, 1, [1, 2, 3], (25, 35), 2),
+            None,
+        ),
+        vector(
+            "different regions",
+            alice(),
+            profile(32, 1, 2, [1, 2, 3], (25, 35), 0),
+            None,
+        ),
+        vector(
+            "different regions, both anywhere",
+            located(alice(), "u09tv", 3),
+            located(profile(32, 1, 2, [1, 2, 3], (25, 35), 0), "dr5ru", 3),
+            Some(1),
+        ),
+        vector(
+            "nearby, same ~40km cell",
+            located(alice(), "u09tv", 1),
+            located(bob(), "u09tu", 1),
+            Some(1),
+        ),
+        vector(
+            "nearby, different cells",
+            located(alice(), "u09tv", 1),
+            located(bob(), "u0d2x", 1),
+            None,
+        ),
+        vector(
+            "wide, same ~150km cell",
+            located(alice(), "u09tv", 2),
+            located(bob(), "u09wh", 2),
+            Some(1),
+        ),
+        vector(
+            "nearby and anywhere, the narrowest band applies",
+            located(alice(), "u09tv", 1),
+            located(bob(), "u09wh", 3),
+            None,
+        ),
+        vector(
+            "nearby without a location",
+            located(alice(), "u09tv", 1),
+            bob(),
+            None,
+        ),
+        vector(
+            "location out of range",
+            User {
+                location: 1 << 26,
+                ..located(alice(), "u09tv", 3)
+            },
+            located(bob(), "u09tv", 3),
+            None,
+        ),
+        vector(
+            "no interest in common, only the hard filters pass",
+            alice(),
+            profile(32, 1, 1, [1, 3, 5], (25, 35), 0),
+            Some(0),
+        ),
+        vector(
+            "last interests in common",
+            alice(),
+            profile(32, 1, 1, [1, 3, 6], (25, 35), 0),
+            Some(1),
+        ),
+        vector(
+            "interest out of range",
+            profile(30, 0, 1, [9, 4, 6], (25, 35), 1),
+            profile(32, 1, 1, [9, 3, 5], (25, 35), 0),
+            None,
+        ),
+        vector(
+            "all interests in common",
+            alice(),
+            profile(32, 1, 1, [6, 4, 2], (25, 35), 0),
+            Some(3),
+        ),
+        vector(
+            "duplicate interests count once",
+            profile(30, 0, 1, [2, 2, 2], (25, 35), 1),
+            profile(32, 1, 1, [2, 2, 3], (25, 35), 0),
+            Some(1),
+        ),
+        vector("same id", same_id.0, same_id.1, None),
+    ]
+}
+
+#[test]
+fn test_predicate_vectors() {
+    for v in vectors() {
+        assert_eq!(score(&v.user1, &v.user2), v.expected, "{}", v.name);
+        assert_eq!(
+            score(&v.user2, &v.user1),
+            v.expected,
+            "{} (swapped)",
+            v.name
+        );
+    }
+}
+
+#[test]
+fn test_mpc_vectors() {
+    let server = common::server();
+    for v in vectors() {
+        assert_eq!(
+            server.evaluate(&v.user1, &v.user2),
+            v.expected,
+            "{}",
+            v.name
+        );
+    }
+}
+
+fn user() -> impl Strategy<Value = User> {
+    (
+        20u32..=40,
+        0u32..=1,
+        0u32..=4,
+        // interests out of range
+        [0u32..=9, 0u32..=9, 0u32..=9],
+        (20u32..=40, 20u32..=40),
+        0u32..=2,
+        // cells sharing more or less of their prefix, or no location
+        prop::sample::select(vec!["", "u09tv", "u09tu", "u09wh", "u0d2x"]),
+        0u32..=3,
+    )
+        .prop_map(
+            |(age, gender, region, interests, (a, b), preferred_gender, cell, distance)| {
+                let user = profile(
+                    age,
+                    gender,
+                    region,
+                    interests,
+                    (a.min(b), a.max(b)),
+                    preferred_gender,
+                );
+                match cell {
+                    "" => User {
+                        preferences: Preferences {
+                            distance,
+                            ..user.preferences
+                        },
+                        ..user
+                    },
+                    cell => located(user, cell, distance),
+                }
+            },
+        )
+}
+
+proptest! {
+    // every case is a full MPC run
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn test_mpc_matches_predicate(user1 in user(), user2 in user()) {
+        let mpc = common::server().evaluate(&user1, &user2);
+        prop_assert_eq!(mpc, score(&user1, &user2));
+    }
+}
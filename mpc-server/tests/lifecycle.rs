@@ -0,0 +1,118 @@
+mod common;
+
+use mpc_server::{config::config, db::connect_db, reveal, token::now};
+use serde_json::json;
+
+use common::profile;
+
+// Its own test binary: expiring the matches affects every match of the server.
+
+#[tokio::test]
+async fn test_unmatch_and_expiry() {
+    let server = common::server();
+    let client = server.client();
+
+    // alice matches bob and carol
+    let bob_profile = profile(32, 1, 1, [1, 2, 3], (25, 35), 0);
+    let alice = server
+        .upload("alice", &profile(30, 0, 1, [2, 4, 6], (25, 35), 1))
+        .await;
+    let bob = server.upload("bob", &bob_profile).await;
+    let carol = server
+        .upload("carol", &profile(33, 1, 1, [4, 6, 8], (25, 35), 0))
+        .await;
+    server.run_matches(&alice).await;
+    let matches = server.match_list(&alice).await;
+    let (carol_match, bob_match) = (
+        matches[0]["id"].as_u64().unwrap(),
+        matches[1]["id"].as_u64().unwrap(),
+    );
+    assert!(matches.iter().all(|m| m["expires_at"].is_u64()));
+
+    // only a revealed match can be unmatched
+    assert_eq!(
+        server.respond(&alice, bob_match, "unmatch").await.status(),
+        409
+    );
+    server.respond(&alice, bob_match, "accept").await;
+    server.respond(&bob, bob_match, "accept").await;
+    assert_eq!(server.matches(&alice).await, [("bob".to_string(), 1)]);
+
+    // unmatching hides the contacts and closes the chat, for both
+    let unmatched: serde_json::Value = server
+        .respond(&bob, bob_match, "unmatch")
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(unmatched["state"], "unmatched");
+    assert_eq!(unmatched["contacts"], json!([]));
+    assert!(server.match_list(&bob).await.is_empty());
+    let alice_unmatched = server.match_list_in(&alice, "unmatched").await;
+    assert_eq!(alice_unmatched.len(), 1);
+    assert_eq!(alice_unmatched[0]["id"], bob_match);
+    assert_eq!(alice_unmatched[0]["contacts"], json!([]));
+    let message = client
+        .post(format!("{}/matches/{bob_match}/messages", server.url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"ciphertext": "aGVsbG8="}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(message.status(), 409);
+    assert_eq!(
+        server.respond(&alice, bob_match, "accept").await.status(),
+        409
+    );
+
+    // new shares don't bring the match back
+    server.replace_shares(&bob, &bob_profile).await;
+    server.run_matches(&bob).await;
+    assert!(server.match_list(&bob).await.is_empty());
+    assert_eq!(server.match_list_in(&bob, "unmatched").await.len(), 1);
+
+    // matches not accepted by both in time expire, even if one user accepted
+    server.respond(&alice, carol_match, "accept").await;
+    assert_eq!(reveal::expire_stale(now()).unwrap(), 0);
+    let expired = reveal::expire_stale(now() + config().match_ttl_secs + 1).unwrap();
+    assert_eq!(expired, 1);
+    assert!(server.match_list(&carol).await.is_empty());
+    let carol_expired = server.match_list_in(&carol, "expired").await;
+    assert_eq!(carol_expired.len(), 1);
+    assert!(carol_expired[0].get("expires_at").is_none());
+    assert_eq!(
+        server.respond(&carol, carol_match, "accept").await.status(),
+        409
+    );
+
+    assert_eq!(server.match_list_in(&alice, "all").await.len(), 2);
+    let invalid = client
+        .get(format!("{}/matches?state=declined", server.url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), 400);
+
+    // a match past its expiry can't be accepted, even before the sweeper runs
+    let dave = server
+        .upload("dave", &profile(31, 1, 1, [2, 5, 7], (25, 35), 0))
+        .await;
+    server.run_matches(&dave).await;
+    let dave_match = server.match_list(&dave).await[0]["id"].as_u64().unwrap();
+    connect_db()
+        .unwrap()
+        .execute(
+            "UPDATE matches SET created_at = created_at - ?1 WHERE id = ?2",
+            (config().match_ttl_secs + 1, dave_match),
+        )
+        .unwrap();
+    assert_eq!(
+        server.respond(&dave, dave_match, "accept").await.status(),
+        409
+    );
+    assert!(server.match_list(&dave).await.is_empty());
+    assert_eq!(server.match_list_in(&dave, "expired").await.len(), 1);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+use tracing::Level;
+use tracing_subscriber::fmt::{MakeWriter, format::FmtSpan};
+
+use common::profile;
+
+/// Collects everything written by the fmt subscriber
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLogs {
+    type Writer = CapturedLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[tokio::test]
+async fn test_logs_are_redacted() {
+    // before the server starts, so its thread logs here too
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(Level::TRACE)
+        .with_span_events(FmtSpan::FULL)
+        .with_ansi(false)
+        .with_writer(logs.clone())
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let server = common::server();
+
+    let alice = profile(30, 0, 1, [2, 4, 6], (25, 35), 1);
+    let bob = profile(32, 1, 1, [1, 2, 3], (25, 35), 0);
+
+    let shares1 = server.split(&alice).await;
+    let shares2 = server.split(&bob).await;
+    let tokens1 = server.upload_shares("secret_alice", &shares1).await;
+    let tokens2 = server.upload_shares("secret_bob", &shares2).await;
+    server.run_matches(&tokens2).await;
+    server.accept_all(&tokens1).await;
+    server.accept_all(&tokens2).await;
+    assert_eq!(
+        server.matches(&tokens2).await,
+        [("secret_alice".to_string(), 1)]
+    );
+
+    let refreshed: common::Tokens = server
+        .client()
+        .post(format!("{}/auth/refresh", server.url))
+        .json(&serde_json::json!({"refresh_token": tokens2.refresh_token}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("match session done"), "nothing was logged");
+
+    let mut secrets = vec![
+        "secret_alice".to_string(),
+        "secret_bob".to_string(),
+        "age_max".to_string(),
+        "interests".to_string(),
+    ];
+    secrets.extend([alice.id, bob.id]);
+    for tokens in [&tokens1, &tokens2, &refreshed] {
+        secrets.push(tokens.token.clone());
+        secrets.push(tokens.refresh_token.clone());
+    }
+    // a prefix is enough to find a leaked share
+    secrets.extend(shares1.iter().chain(&shares2).map(|s| s[..32].to_string()));
+
+    for secret in secrets {
+        assert!(
+            !logs.contains(&secret),
+            "secret leaked to the logs: {secret}"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use serde_json::{Value, json};
+
+use common::{ADMIN_TOKEN, TestServer, Tokens, profile};
+
+async fn report(server: &TestServer, tokens: &Tokens, match_id: u64, body: Value) -> u16 {
+    server
+        .client()
+        .post(format!("{}/matches/{match_id}/report", server.url))
+        .bearer_auth(&tokens.token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+async fn admin(request: reqwest::RequestBuilder, token: &str) -> Value {
+    let response = request.bearer_auth(token).send().await.unwrap();
+    let status = response.status().as_u16();
+    let body: Value = response.json().await.unwrap();
+    json!({"status": status, "body": body})
+}
+
+#[tokio::test]
+async fn test_block_and_report() {
+    let server = common::server();
+    let client = server.client();
+
+    // alice matches bob and carol
+    let alice_profile = profile(30, 0, 1, [2, 4, 6], (25, 35), 1);
+    let bob_profile = profile(32, 1, 1, [1, 2, 3], (25, 35), 0);
+    let alice = server.upload("alice", &alice_profile).await;
+    let bob = server.upload("bob", &bob_profile).await;
+    let carol = server
+        .upload("carol", &profile(33, 1, 1, [4, 6, 8], (25, 35), 0))
+        .await;
+    server.run_matches(&alice).await;
+    let alice_matches = server.match_list(&alice).await;
+    let (carol_match, bob_match) = (
+        alice_matches[0]["id"].as_u64().unwrap(),
+        alice_matches[1]["id"].as_u64().unwrap(),
+    );
+
+    // a blocked match disappears for both, and isn't found again after new shares
+    server
+        .client()
+        .post(format!("{}/matches/{bob_match}/block", server.url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(server.match_list(&alice).await.len(), 1);
+    assert!(server.match_list(&bob).await.is_empty());
+    assert_eq!(
+        server.respond(&bob, bob_match, "accept").await.status(),
+        404
+    );
+    server.replace_shares(&bob, &bob_profile).await;
+    server.run_matches(&bob).await;
+    assert!(server.match_list(&bob).await.is_empty());
+
+    // carol reports alice, which also blocks her
+    let too_long = json!({"reason": "other", "details": "a".repeat(1001)});
+    assert_eq!(report(server, &carol, carol_match, too_long).await, 400);
+    let details = json!({"reason": "harassment", "details": "rude messages"});
+    assert_eq!(
+        report(server, &carol, carol_match, details.clone()).await,
+        200
+    );
+    assert!(server.match_list(&carol).await.is_empty());
+    assert_eq!(report(server, &carol, carol_match, details).await, 404);
+
+    // the queue is only readable with the admin token
+    let reports_url = format!("{}/admin/reports", server.url);
+    let response = admin(client.get(&reports_url), "wrong").await;
+    assert_eq!(response["status"], 401);
+    assert_eq!(
+        server
+            .client()
+            .get(&reports_url)
+            .bearer_auth(&alice.token)
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        401
+    );
+
+    let response = admin(client.get(&reports_url), ADMIN_TOKEN).await;
+    let reports = response["body"]["reports"].as_array().unwrap();
+    assert_eq!(reports.len(), 1);
+    let queued = &reports[0];
+    assert_eq!(queued["match_id"], carol_match);
+    assert_eq!(queued["reason"], "harassment");
+    assert_eq!(queued["details"], "rude messages");
+    assert_eq!(queued["status"], "open");
+    assert_eq!(queued["reports_against"], 1);
+    assert_eq!(queued["reported_deleted"], false);
+    // nothing about the preferences
+    assert!(queued.get("score").is_none());
+
+    // suspending alice hides her and stops her matching
+    let review_url = format!("{reports_url}/{}", queued["id"]);
+    let suspend = client.post(&review_url).json(&json!({"action": "suspend"}));
+    let response = admin(suspend, ADMIN_TOKEN).await;
+    assert_eq!(response["body"]["status"], "actioned");
+    let dismiss = client.post(&review_url).json(&json!({"action": "dismiss"}));
+    assert_eq!(admin(dismiss, ADMIN_TOKEN).await["status"], 409);
+
+    let open = admin(client.get(&reports_url), ADMIN_TOKEN).await;
+    assert_eq!(open["body"]["reports"], json!([]));
+    let actioned = client.get(format!("{reports_url}?status=actioned"));
+    let actioned = admin(actioned, ADMIN_TOKEN).await;
+    assert_eq!(actioned["body"]["reports"].as_array().unwrap().len(), 1);
+
+    assert!(server.match_list(&alice).await.is_empty());
+    let response = server
+        .client()
+        .post(format!("{}/matches", server.url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    // deleting the account doesn't delete the reports against it
+    client
+        .delete(format!("{}/account", server.url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let actioned = client.get(format!("{reports_url}?status=actioned"));
+    let actioned = admin(actioned, ADMIN_TOKEN).await;
+    assert_eq!(actioned["body"]["reports"][0]["reported_deleted"], true);
+}
@@ -0,0 +1,138 @@
+use mpc_server::{
+    geohash,
+    profile::{self, CURRENT_VERSION, DistanceBand},
+};
+use serde_json::{Value, json};
+
+fn v1() -> Value {
+    json!({
+        "id": "0x01",
+        "age": 30,
+        "gender": 0,
+        "region": 1,
+        "interests": [2, 4, 4],
+        "preferences": {"age_min": 25, "age_max": 35, "gender": 2},
+    })
+}
+
+fn v2() -> Value {
+    json!({
+        "id": "0x01",
+        "age": 30,
+        "gender": 0,
+        "region": 1,
+        "interests": [
+            {"id": 1, "weight": 2},
+            {"id": 5, "weight": 9},
+            {"id": 7, "weight": 1},
+            {"id": 3, "weight": 5},
+        ],
+        "preferences": {"age_min": 25, "age_max": 35, "genders": [1]},
+    })
+}
+
+#[test]
+fn test_v1_is_migrated() {
+    let profile = profile::parse(1, v1()).unwrap();
+    assert_eq!(profile.preferences.genders, [0, 1]);
+    assert_eq!(profile.interests.len(), 2);
+    assert_eq!(profile.preferences.distance, DistanceBand::SameRegion);
+
+    let user = profile.to_circuit_input().unwrap();
+    assert_eq!(user.preferences.gender, 2);
+    assert_eq!(user.interests, [2, 4, 2]);
+}
+
+#[test]
+fn test_v2_to_circuit_input() {
+    let user = profile::parse(CURRENT_VERSION, v2())
+        .unwrap()
+        .to_circuit_input()
+        .unwrap();
+    assert_eq!(user.preferences.gender, 1);
+    // the 3 heaviest
+    assert_eq!(user.interests, [5, 3, 1]);
+}
+
+#[test]
+fn test_invalid_profiles() {
+    let invalid = [
+        (1, json!({"gender": 2})),
+        (1, json!({"region": 9})),
+        (1, json!({"interests": [9, 1, 2]})),
+        (1, json!({"age": 300})),
+        (
+            1,
+            json!({"preferences": {"age_min": 40, "age_max": 30, "gender": 0}}),
+        ),
+        (2, json!({"interests": []})),
+        (2, json!({"interests": [{"id": 1, "weight": 0}]})),
+        (
+            2,
+            json!({"interests": [{"id": 1, "weight": 1}, {"id": 1, "weight": 2}]}),
+        ),
+        (
+            2,
+            json!({"preferences": {"age_min": 25, "age_max": 35, "genders": []}}),
+        ),
+        (
+            2,
+            json!({"preferences": {"age_min": 25, "age_max": 35, "genders": [0, 0]}}),
+        ),
+        // the client sends a geohash instead
+        (2, json!({"location": {"lat": 48.8566, "lon": 2.3522}})),
+        (2, json!({"location": {"geohash": "u09"}})),
+        (2, json!({"location": {"geohash": "u09ta"}})),
+        (2, json!({"location": {"city": "Atlantis"}})),
+        // Tokyo is in region 3
+        (2, json!({"location": {"city": "Tokyo"}})),
+        (3, json!({})),
+    ];
+    for (version, patch) in invalid {
+        let mut profile = if version == 1 { v1() } else { v2() };
+        for (key, value) in patch.as_object().unwrap() {
+            profile[key] = value.clone();
+        }
+        assert!(
+            profile::parse(version, profile).is_err(),
+            "v{version} {patch} is valid"
+        );
+    }
+}
+
+#[test]
+fn test_unsupported_by_circuit() {
+    let mut profile = v2();
+    profile["preferences"] =
+        json!({"age_min": 25, "age_max": 35, "genders": [1], "distance": "nearby"});
+    let profile = profile::parse(CURRENT_VERSION, profile).unwrap();
+    assert!(profile.to_circuit_input().is_err());
+
+    // the circuit has no per-user dealbreakers, they are rejected rather than ignored
+    let mut profile = v2();
+    profile["preferences"]["dealbreakers"] = json!(["age"]);
+    assert!(profile::parse(CURRENT_VERSION, profile).is_err());
+}
+
+#[test]
+fn test_locations() {
+    let paris = geohash::circuit_cell(geohash::parse("u09tv").unwrap());
+    for location in [json!({"city": "paris"}), json!({"geohash": "u09tvw0"})] {
+        let mut profile = v2();
+        profile["location"] = location.clone();
+        profile["preferences"]["distance"] = json!("nearby");
+        let user = profile::parse(CURRENT_VERSION, profile)
+            .unwrap()
+            .to_circuit_input()
+            .unwrap();
+        assert_eq!(user.location, paris, "{location}");
+        assert_eq!(user.preferences.distance, 1);
+    }
+
+    // without a location, only the region is compared
+    let user = profile::parse(CURRENT_VERSION, v2())
+        .unwrap()
+        .to_circuit_input()
+        .unwrap();
+    assert_eq!(user.location, 0);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use mpc_server::{config::config, crypto, token::verify};
+use serde_json::{Value, json};
+
+use common::profile;
+
+#[tokio::test]
+async fn test_replace_and_rollback_shares() {
+    let server = common::server();
+    let client = server.client();
+
+    let alice = profile(30, 0, 1, [2, 4, 6], (25, 35), 1);
+    let bob = profile(32, 1, 1, [1, 2, 3], (25, 35), 0);
+    // alice first uploads a profile which is too young for bob
+    let young_alice = profile(22, 0, 1, [2, 4, 6], (20, 35), 1);
+
+    let alice_tokens = server.upload("alice", &young_alice).await;
+    let bob_tokens = server.upload("bob", &bob).await;
+    server.run_matches(&bob_tokens).await;
+    assert!(server.matches(&alice_tokens).await.is_empty());
+
+    // same account, new shares, and the pair is evaluated again
+    assert_eq!(server.replace_shares(&alice_tokens, &alice).await, 1);
+    server.run_matches(&alice_tokens).await;
+    server.accept_all(&alice_tokens).await;
+    server.accept_all(&bob_tokens).await;
+    let expected = vec![("bob".to_string(), 1)];
+    assert_eq!(server.matches(&alice_tokens).await, expected);
+
+    // the previous shares can be restored, the match is kept
+    let versions: Value = client
+        .get(format!("{}/profile/shares", server.url))
+        .bearer_auth(&alice_tokens.token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(versions["versions"].as_array().unwrap().len(), 2);
+
+    client
+        .post(format!("{}/profile/shares/rollback", server.url))
+        .bearer_auth(&alice_tokens.token)
+        .json(&json!({ "version": 0 }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let status: Value = client
+        .get(format!("{}/profile", server.url))
+        .bearer_auth(&alice_tokens.token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["share_version"], 0);
+    server.run_matches(&alice_tokens).await;
+    assert_eq!(server.matches(&alice_tokens).await, expected);
+
+    // a match which isn't revealed yet is dropped with the shares it was scored with
+    let carol = profile(31, 1, 1, [2, 3, 5], (25, 35), 0);
+    let carol_tokens = server.upload("carol", &carol).await;
+    assert_eq!(server.replace_shares(&alice_tokens, &alice).await, 2);
+    server.run_matches(&carol_tokens).await;
+    server.accept_all(&carol_tokens).await;
+    assert_eq!(
+        server.match_list(&carol_tokens).await[0]["state"],
+        "accepted"
+    );
+    client
+        .post(format!("{}/profile/shares/rollback", server.url))
+        .bearer_auth(&alice_tokens.token)
+        .json(&json!({ "version": 0 }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    server.run_matches(&carol_tokens).await;
+    assert!(server.match_list(&carol_tokens).await.is_empty());
+    assert_eq!(server.matches(&alice_tokens).await, expected);
+
+    let unknown = client
+        .post(format!("{}/profile/shares/rollback", server.url))
+        .bearer_auth(&alice_tokens.token)
+        .json(&json!({ "version": 7 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), 404);
+}
+
+#[tokio::test]
+async fn test_plaintext_shares() {
+    let server = common::server();
+
+    // in region 2, away from the users of the other test
+    let alice = profile(30, 0, 2, [2, 4, 6], (25, 35), 1);
+    let bob = profile(32, 1, 2, [1, 2, 3], (25, 35), 0);
+    let alice_tokens = server.upload("alice", &alice).await;
+    let bob_tokens = server.upload("bob", &bob).await;
+
+    // shares written before encryption at rest are still matched
+    let user_id = verify(&alice_tokens.token).unwrap().user_id;
+    for dir in [config().shares_dir_1(), config().shares_dir_2()] {
+        for i in 0..3 {
+            let path = dir.join(format!("{user_id}-{i}"));
+            let plaintext = crypto::open(&std::fs::read(&path).unwrap()).unwrap();
+            std::fs::write(&path, plaintext).unwrap();
+        }
+    }
+    server.run_matches(&bob_tokens).await;
+    assert_eq!(server.match_list(&alice_tokens).await.len(), 1);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use mpc_server::reveal::{Action, RevealState};
+
+use common::profile;
+
+#[test]
+fn test_transitions() {
+    use RevealState::*;
+
+    let cases = [
+        (Pending, true, Action::Accept, Some(AcceptedByUser1)),
+        (Pending, false, Action::Accept, Some(AcceptedByUser2)),
+        (AcceptedByUser1, true, Action::Accept, Some(AcceptedByUser1)),
+        (AcceptedByUser1, false, Action::Accept, Some(Revealed)),
+        (AcceptedByUser2, true, Action::Accept, Some(Revealed)),
+        (Pending, false, Action::Decline, Some(Declined)),
+        (AcceptedByUser1, true, Action::Decline, Some(Declined)),
+        (AcceptedByUser1, false, Action::Decline, Some(Declined)),
+        (Revealed, true, Action::Accept, Some(Revealed)),
+        (Revealed, false, Action::Decline, None),
+        (Declined, true, Action::Accept, None),
+        (Revealed, false, Action::Unmatch, Some(Unmatched)),
+        (Unmatched, true, Action::Unmatch, Some(Unmatched)),
+        (Unmatched, true, Action::Accept, None),
+        (Pending, true, Action::Unmatch, None),
+        (AcceptedByUser2, true, Action::Unmatch, None),
+        (Expired, false, Action::Accept, None),
+        (Expired, true, Action::Decline, None),
+    ];
+    for (state, is_user1, action, expected) in cases {
+        assert_eq!(
+            state.next(is_user1, action).ok(),
+            expected,
+            "{state:?} {action:?} by user{}",
+            if is_user1 { 1 } else { 2 }
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_mutual_reveal() {
+    let server = common::server();
+
+    // alice matches bob and carol
+    let alice = server
+        .upload("alice", &profile(30, 0, 1, [2, 4, 6], (25, 35), 1))
+        .await;
+    let bob = server
+        .upload("bob", &profile(32, 1, 1, [1, 2, 3], (25, 35), 0))
+        .await;
+    let carol = server
+        .upload("carol", &profile(33, 1, 1, [4, 6, 8], (25, 35), 0))
+        .await;
+    server.run_matches(&alice).await;
+
+    let alice_matches = server.match_list(&alice).await;
+    assert_eq!(alice_matches.len(), 2);
+    assert!(alice_matches.iter().all(|m| m["state"] == "pending"));
+    assert!(
+        alice_matches
+            .iter()
+            .all(|m| m.get("twitter_handle").is_none())
+    );
+    // best first
+    let (carol_match, bob_match) = (
+        alice_matches[0]["id"].as_u64().unwrap(),
+        alice_matches[1]["id"].as_u64().unwrap(),
+    );
+
+    // bob doesn't know alice accepted, and nothing is revealed until he does
+    server
+        .respond(&alice, bob_match, "accept")
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(server.match_list(&alice).await[1]["state"], "accepted");
+    let bob_matches = server.match_list(&bob).await;
+    assert_eq!(bob_matches[0]["state"], "pending");
+    assert!(bob_matches[0].get("twitter_handle").is_none());
+
+    server
+        .respond(&bob, bob_match, "accept")
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(server.matches(&bob).await, [("alice".to_string(), 1)]);
+    assert_eq!(server.matches(&alice).await, [("bob".to_string(), 1)]);
+    assert_eq!(
+        server.respond(&bob, bob_match, "decline").await.status(),
+        409
+    );
+
+    // carol can't answer for bob's match
+    assert_eq!(
+        server.respond(&carol, bob_match, "accept").await.status(),
+        404
+    );
+
+    // a declined match disappears for both
+    server
+        .respond(&alice, carol_match, "accept")
+        .await
+        .error_for_status()
+        .unwrap();
+    server
+        .respond(&carol, carol_match, "decline")
+        .await
+        .error_for_status()
+        .unwrap();
+    assert!(server.match_list(&carol).await.is_empty());
+    assert_eq!(server.match_list(&alice).await.len(), 1);
+    assert_eq!(
+        server.respond(&carol, carol_match, "accept").await.status(),
+        404
+    );
+}
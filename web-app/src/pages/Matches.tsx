@@ -1,7 +1,8 @@
 import { useState, useEffect } from "react";
-import { getMatches, postMatches } from "./api";
+import { getMatches, postMatches, respondToMatch } from "./api";
 
-type Match = string;
+// the handle is only sent once both users accepted
+type Match = { id: number; state: "pending" | "accepted" | "revealed"; twitter_handle?: string; score: number };
 
 export default function Matches() {
   const [matches, setMatches] = useState<Match[]>([]);
@@ -40,6 +41,21 @@ export default function Matches() {
     }
   };
 
+  const respond = async (id: number, action: "accept" | "decline") => {
+    const token = localStorage.getItem("co-match-token");
+    if (!token) {
+      setError("No token found. Please try uploading your shares again.");
+      return;
+    }
+
+    try {
+      await respondToMatch(token, id, action);
+      fetchMatches();
+    } catch (error) {
+      console.error(error);
+    }
+  };
+
   const computeNewMatches = async () => {
     const token = localStorage.getItem("co-match-token");
     if (!token) {
@@ -144,14 +160,50 @@ export default function Matches() {
       </div>
       <div className="space-y-4">
         {matches.map((match_) => {
-          const handle = match_.startsWith("@") ? match_.slice(1) : match_;
+          const interests = `${match_.score} interest${match_.score === 1 ? "" : "s"} in common`;
+          if (!match_.twitter_handle) {
+            return (
+              <div key={match_.id} className="border border-gray-200 rounded-xl p-6 hover:border-purple-300 transition-all hover:shadow-md">
+                <div className="flex items-center justify-between">
+                  <div>
+                    <h3 className="text-xl font-semibold text-gray-900">Someone matched with you 👀</h3>
+                    <p className="text-sm text-gray-500">
+                      {interests}.{" "}
+                      {match_.state === "accepted"
+                        ? "Waiting for them to accept..."
+                        : "Handles are only revealed if you both accept."}
+                    </p>
+                  </div>
+                  {match_.state === "pending" && (
+                    <div className="flex gap-3">
+                      <button
+                        onClick={() => respond(match_.id, "decline")}
+                        className="px-4 py-2 border border-purple-600 text-purple-700 rounded-xl hover:bg-purple-50 transition-all text-sm font-medium"
+                      >
+                        Decline
+                      </button>
+                      <button
+                        onClick={() => respond(match_.id, "accept")}
+                        className="px-4 py-2 rounded-xl transition-all duration-300 bg-gradient-to-r from-pink-500 to-purple-500 text-white hover:from-pink-600 hover:to-purple-600 shadow-md hover:shadow-lg transform hover:-translate-y-0.5 text-sm font-medium"
+                      >
+                        Accept
+                      </button>
+                    </div>
+                  )}
+                </div>
+              </div>
+            );
+          }
+          const handle = match_.twitter_handle.startsWith("@") ? match_.twitter_handle.slice(1) : match_.twitter_handle;
 
           return (
-            <div key={match_} className="border border-gray-200 rounded-xl p-6 hover:border-purple-300 transition-all hover:shadow-md">
+            <div key={match_.id} className="border border-gray-200 rounded-xl p-6 hover:border-purple-300 transition-all hover:shadow-md">
               <div className="flex items-center justify-between">
                 <div>
                   <h3 className="text-xl font-semibold text-gray-900">@{handle}</h3>
-                  <p className="text-sm text-gray-500">You have a match! 🎉</p>
+                  <p className="text-sm text-gray-500">
+                    You have a match! 🎉 {interests}
+                  </p>
                 </div>
                 <div className="flex gap-3">
                   <a
@@ -1,5 +1,5 @@
 import { useState } from "react";
-import { postShares, splitPreferences } from "./api";
+import { postShares, splitPreferences, storeTokens } from "./api";
 
 interface UserPreferences {
   id: string;
@@ -75,10 +75,8 @@ export default function Shares({ preferences, getMatches }: SharesProps) {
       if (data.error) {
         setUploadError(data.error);
       } else {
-        // Store the token in localStorage
-        if (data.token) {
-          localStorage.setItem("co-match-token", data.token);
-        }
+        // Store the tokens in localStorage
+        storeTokens(data);
         setUploadComplete(true);
       }
     } catch (error) {
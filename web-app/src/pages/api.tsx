@@ -2,23 +2,64 @@ import { UserPreferences } from "./Preferences";
 
 const API_URL = process.env.NEXT_PUBLIC_API_URL;
 
-export const getMatches = async (token: string) => {
-  const response = await fetch(`${API_URL}/matches`, {
-    method: "GET",
+export const storeTokens = (data: { token?: string; refresh_token?: string }) => {
+  if (data.token) {
+    localStorage.setItem("co-match-token", data.token);
+  }
+  if (data.refresh_token) {
+    localStorage.setItem("co-match-refresh-token", data.refresh_token);
+  }
+};
+
+const refreshToken = async (): Promise<string | null> => {
+  const refresh_token = localStorage.getItem("co-match-refresh-token");
+  // tokens issued before refresh tokens are exchanged once for a token pair
+  const legacy_token = refresh_token ? null : localStorage.getItem("co-match-token");
+  if (!refresh_token && !legacy_token) return null;
+
+  const response = await fetch(`${API_URL}/auth/refresh`, {
+    method: "POST",
     headers: {
-      Authorization: `Bearer ${token}`,
+      "Content-Type": "application/json",
     },
+    body: JSON.stringify(refresh_token ? { refresh_token } : { legacy_token }),
   });
+  if (!response.ok) return null;
+
+  const data = await response.json();
+  storeTokens(data);
+  return data.token;
+};
+
+// Access tokens are short-lived: if the server rejects it, refresh it once and retry
+const authFetch = async (path: string, method: string, token: string) => {
+  const send = (token: string) =>
+    fetch(`${API_URL}${path}`, {
+      method,
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    });
+
+  const response = await send(token);
+  if (response.status !== 400 && response.status !== 401) return response;
+
+  const newToken = await refreshToken();
+  return newToken ? send(newToken) : response;
+};
+
+export const getMatches = async (token: string) => {
+  const response = await authFetch("/matches", "GET", token);
   return response.json();
 };
 
 export const postMatches = async (token: string) => {
-  const response = await fetch(`${API_URL}/matches`, {
-    method: "POST",
-    headers: {
-      Authorization: `Bearer ${token}`,
-    },
-  });
+  const response = await authFetch("/matches", "POST", token);
+  return response.json();
+};
+
+export const respondToMatch = async (token: string, id: number, action: "accept" | "decline") => {
+  const response = await authFetch(`/matches/${id}/${action}`, "POST", token);
   return response.json();
 };
 